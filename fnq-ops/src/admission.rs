@@ -0,0 +1,85 @@
+use std::{fs, io, path, thread, time};
+
+use crate::{IOResultExt, OpsError};
+
+const MEMINFO_PATH: &str = "/proc/meminfo";
+const LOADAVG_PATH: &str = "/proc/loadavg";
+
+// How often a task held back by `--min-memory` or `--max-load` looks again. The kernel updates the
+// load average every 5 seconds anyway
+const ADMISSION_POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// `MemAvailable` of `/proc/meminfo`, in bytes. It counts the page cache and other memory that can
+/// be reclaimed, unlike `MemFree`
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    kib.checked_mul(1024)
+}
+
+/// 1 minute load average of `/proc/loadavg`
+fn parse_loadavg(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+fn read_proc<T>(proc_path: &str, parse: fn(&str) -> Option<T>) -> Result<T, OpsError> {
+    let proc_path = path::Path::new(proc_path);
+    let text = fs::read_to_string(proc_path).path_context("read", proc_path)?;
+    parse(&text).ok_or_else(|| {
+        OpsError::PathIO(
+            "parse",
+            proc_path.into(),
+            io::Error::new(io::ErrorKind::InvalidData, "unexpected format"),
+        )
+    })
+}
+
+/// Whether at least `min_memory` bytes are available and the load average is below `max_load`.
+/// Leaving both out always admits
+pub(crate) fn admits(min_memory: Option<u64>, max_load: Option<f64>) -> Result<bool, OpsError> {
+    if let Some(min_memory) = min_memory {
+        if read_proc(MEMINFO_PATH, parse_meminfo)? < min_memory {
+            return Ok(false);
+        }
+    }
+    if let Some(max_load) = max_load {
+        if read_proc(LOADAVG_PATH, parse_loadavg)? >= max_load {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Blocks until `admits` does
+pub(crate) fn wait_for_resources(
+    min_memory: Option<u64>,
+    max_load: Option<f64>,
+) -> Result<(), OpsError> {
+    while !admits(min_memory, max_load)? {
+        thread::sleep(ADMISSION_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc() {
+        let meminfo = "MemTotal:       16303412 kB\n\
+                       MemFree:          513364 kB\n\
+                       MemAvailable:    8151704 kB\n\
+                       Buffers:          398612 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(8151704 * 1024));
+        assert_eq!(parse_meminfo("MemTotal:       16303412 kB\n"), None);
+        assert_eq!(parse_loadavg("0.52 0.58 0.59 2/1203 30012\n"), Some(0.52));
+        assert_eq!(parse_loadavg(""), None);
+
+        assert!(admits(None, None).unwrap());
+        assert!(admits(Some(0), Some(f64::MAX)).unwrap());
+        assert!(!admits(Some(u64::MAX), None).unwrap());
+    }
+}
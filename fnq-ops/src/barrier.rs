@@ -0,0 +1,56 @@
+use std::io::Write;
+use std::{fs, io, path};
+
+use crate::{files, status, wait_for_status, OpsError};
+
+/// Takes the place of the task command in the queue file of a barrier, so its exec line reads
+/// `exec fnq --barrier` and requeueing it queues a barrier again
+pub const BARRIER_ARG: &str = "--barrier";
+
+fn is_barrier(queue_path: &path::Path) -> Result<bool, OpsError> {
+    match fs::File::open(queue_path) {
+        Ok(queue_file) => Ok(status::read_header(queue_file)?.is_barrier()),
+        // Removed (e.g. by --clean) since it was listed
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Run by a barrier in place of a task once its turn has come. Waits for the status of every job
+/// queued since the previous barrier, writes a line to `out` for each that did not succeed, and
+/// returns whether all of them did. Jobs whose queue file is gone, e.g. through --clean, aren't
+/// counted
+pub fn check(
+    queue_dir: &path::PathBuf,
+    barrier_path: &path::Path,
+    out: &mut impl Write,
+) -> Result<bool, OpsError> {
+    let mut earlier = Vec::new();
+    for entry in files::files(queue_dir)? {
+        if entry.filepath == barrier_path {
+            break;
+        } else if is_barrier(&entry.filepath)? {
+            earlier.clear();
+        } else {
+            earlier.push(entry.filepath);
+        }
+    }
+
+    let mut succeeded = true;
+    for queue_path in earlier {
+        let file_name = queue_path.file_name().unwrap_or_default().to_string_lossy();
+        match wait_for_status(&queue_path)? {
+            Some(job_status) if job_status.succeeded() => {}
+            Some(job_status) => {
+                writeln!(out, "{}\t{}", file_name, job_status)?;
+                succeeded = false;
+            }
+            None if !queue_path.exists() => {}
+            None => {
+                writeln!(out, "{}\tno status", file_name)?;
+                succeeded = false;
+            }
+        }
+    }
+    Ok(succeeded)
+}
@@ -0,0 +1,307 @@
+use std::{env, ffi, fs, io, path, thread, time};
+
+use nix::libc;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::{self, Pid};
+
+use crate::{IOResultExt, OpsError};
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+const PROC_CGROUP_PATH: &str = "/proc/self/cgroup";
+
+// cpu.max is a quota of CPU time per period, and this is the period the kernel defaults to
+const CPU_PERIOD_MICROS: u64 = 100_000;
+
+// How often removing a cgroup checks whether the processes in it have exited, backing off from
+// the first to the second
+const EMPTY_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+const EMPTY_POLL_MAX_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+/// Whether the task runs in a cgroup v2 cgroup of its own, and the limits enforced by that
+/// cgroup's controllers. Unlike `setrlimit` limits, they apply to the task and everything it
+/// spawns taken together
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CgroupLimits {
+    /// `--cgroup`: a cgroup of its own even without limits, so that its memory peak is recorded
+    /// and whatever it leaves behind gets killed with it
+    pub enabled: bool,
+    /// `--memory-max`: memory of the whole cgroup in bytes (`memory.max`). Going over it gets
+    /// processes in it killed by the OOM killer
+    pub memory_max: Option<u64>,
+    /// `--cpu-max`: how many CPUs worth of time the whole cgroup gets (`cpu.max`). Going over it
+    /// throttles it
+    pub cpu_max: Option<f64>,
+}
+
+impl CgroupLimits {
+    /// The fnq flags that would parse back into these limits
+    pub fn to_args(&self) -> Vec<ffi::OsString> {
+        let mut args: Vec<ffi::OsString> = Vec::new();
+        if self.enabled {
+            args.push("--cgroup".into());
+        }
+        if let Some(memory_max) = self.memory_max {
+            args.push("--memory-max".into());
+            args.push(memory_max.to_string().into());
+        }
+        if let Some(cpu_max) = self.cpu_max {
+            args.push("--cpu-max".into());
+            args.push(cpu_max.to_string().into());
+        }
+        args
+    }
+
+    /// Whether the task gets a cgroup of its own, which the limits imply
+    pub fn is_enabled(&self) -> bool {
+        self.enabled || self.memory_max.is_some() || self.cpu_max.is_some()
+    }
+
+    /// Flags that are set along with the controller each needs
+    fn controllers(&self) -> Vec<(&'static str, &'static str)> {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() {
+            controllers.push(("--memory-max", "memory"));
+        }
+        if self.cpu_max.is_some() {
+            controllers.push(("--cpu-max", "cpu"));
+        }
+        controllers
+    }
+
+    /// Fails if the task gets a cgroup but job cgroups can't be created, or won't get the
+    /// controller a limit needs, so that it fails when queueing rather than once the task's turn
+    /// has come
+    pub(crate) fn check(&self) -> Result<(), OpsError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let dir = cgroup_dir().ok_or_else(|| {
+            OpsError::Unknown("Job cgroups need a writable cgroup v2 directory".into())
+        })?;
+        for (flag, controller) in self.controllers() {
+            if !has_controller(&dir, controller) {
+                return Err(OpsError::Unknown(format!(
+                    "{} needs the {} controller enabled in {:?}",
+                    flag,
+                    controller,
+                    dir.join("cgroup.subtree_control")
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Root and mount point of the cgroup v2 hierarchy in `/proc/self/mountinfo`
+fn parse_mountinfo(mountinfo: &str) -> Option<(path::PathBuf, path::PathBuf)> {
+    mountinfo.lines().find_map(|line| {
+        let (mount, source) = line.split_once(" - ")?;
+        if source.split_whitespace().next()? != "cgroup2" {
+            return None;
+        }
+        let mut fields = mount.split_whitespace().skip(3);
+        let root = fields.next()?;
+        let mount_point = fields.next()?;
+        Some((root.into(), mount_point.into()))
+    })
+}
+
+/// Cgroup v2 path of the calling process in `/proc/self/cgroup`, relative to the hierarchy's root
+fn parse_proc_cgroup(proc_cgroup: &str) -> Option<path::PathBuf> {
+    proc_cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(path::PathBuf::from)
+}
+
+/// Space separated list in a cgroup interface file, e.g. `cgroup.controllers`. Empty if it can't
+/// be read
+fn read_list(dir: &path::Path, file: &str) -> Vec<String> {
+    fs::read_to_string(dir.join(file))
+        .map(|list| list.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// Whether the cgroups created in `dir` get `controller`. Controllers are enabled for them in
+/// `dir`'s `cgroup.subtree_control`, which fnq leaves to whoever delegated `dir`
+fn has_controller(dir: &path::Path, controller: &str) -> bool {
+    read_list(dir, "cgroup.subtree_control")
+        .iter()
+        .any(|name| name == controller)
+}
+
+/// Where job cgroups are created: `$FNQ_CGROUP`, else the cgroup fnq runs in. `None` if that
+/// isn't a cgroup v2 dir that can be written to, and with `$FNQ_CGROUP` set but empty
+pub fn cgroup_dir() -> Option<path::PathBuf> {
+    let dir = match env::var_os("FNQ_CGROUP") {
+        Some(dir) if dir.is_empty() => return None,
+        Some(dir) => path::PathBuf::from(dir),
+        None => {
+            let (root, mount_point) = parse_mountinfo(&fs::read_to_string(MOUNTINFO_PATH).ok()?)?;
+            let cgroup = parse_proc_cgroup(&fs::read_to_string(PROC_CGROUP_PATH).ok()?)?;
+            mount_point.join(cgroup.strip_prefix(&root).ok()?)
+        }
+    };
+    let writable = |path: &path::Path| unistd::access(path, unistd::AccessFlags::W_OK).is_ok();
+    if writable(&dir) && writable(&dir.join("cgroup.procs")) {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+/// What the cgroup of a task recorded once it exited
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct CgroupUsage {
+    /// Most memory the task and its processes used at once, where the memory controller is
+    /// enabled
+    pub memory_peak: Option<u64>,
+    /// How many of its processes the OOM killer killed
+    pub oom_kills: u64,
+}
+
+impl CgroupUsage {
+    /// Whether the OOM killer is why the task was killed by `signal`
+    pub(crate) fn exceeded(&self, signal: Signal) -> Option<String> {
+        if signal == Signal::SIGKILL && self.oom_kills > 0 {
+            Some("out of memory".into())
+        } else {
+            None
+        }
+    }
+}
+
+/// `oom_kill` count in `memory.events`
+fn parse_oom_kills(memory_events: &str) -> Option<u64> {
+    memory_events
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The cgroup a task runs in, named after its queue file
+#[derive(Clone)]
+pub(crate) struct JobCgroup {
+    path: path::PathBuf,
+}
+
+impl JobCgroup {
+    pub(crate) fn new(cgroup_dir: &path::Path, task_filename: &ffi::OsStr) -> Self {
+        Self {
+            path: cgroup_dir.join(task_filename),
+        }
+    }
+
+    fn write(&self, file: &str, value: String) -> Result<(), OpsError> {
+        let file_path = self.path.join(file);
+        fs::write(&file_path, value).path_context("write", &file_path)
+    }
+
+    /// Creates the cgroup, applies `limits` to it and moves the calling process into it
+    pub(crate) fn enter(&self, limits: &CgroupLimits) -> Result<(), OpsError> {
+        fs::create_dir(&self.path).path_context("create", &self.path)?;
+
+        if let Some(memory_max) = limits.memory_max {
+            self.write("memory.max", memory_max.to_string())?;
+        }
+        if let Some(cpu_max) = limits.cpu_max {
+            let quota = (cpu_max * CPU_PERIOD_MICROS as f64).ceil() as u64;
+            self.write("cpu.max", format!("{} {}", quota, CPU_PERIOD_MICROS))?;
+        }
+        self.write("cgroup.procs", "0".into())
+    }
+
+    /// Kills every process left in the cgroup
+    pub(crate) fn kill(&self) -> Result<(), OpsError> {
+        match fs::write(self.path.join("cgroup.kill"), "1") {
+            // Linux before 5.14 has no cgroup.kill, so its processes are killed one by one
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.signal(Signal::SIGKILL);
+                Ok(())
+            }
+            result => result.path_context("write", &self.path.join("cgroup.kill")),
+        }
+    }
+
+    /// Sends `signal` to every process in the cgroup, including ones that left the task's
+    /// process group
+    pub(crate) fn signal(&self, signal: Signal) {
+        for pid in read_list(&self.path, "cgroup.procs") {
+            if let Ok(pid) = pid.parse() {
+                let _ = signal::kill(Pid::from_raw(pid), signal);
+            }
+        }
+    }
+
+    /// Reads the usage the cgroup recorded. `None` if the task never got to create it
+    pub(crate) fn usage(&self) -> Option<CgroupUsage> {
+        if !self.path.is_dir() {
+            return None;
+        }
+        let read = |file: &str| fs::read_to_string(self.path.join(file)).ok();
+        Some(CgroupUsage {
+            memory_peak: read("memory.peak").and_then(|peak| peak.trim().parse().ok()),
+            oom_kills: read("memory.events")
+                .and_then(|events| parse_oom_kills(&events))
+                .unwrap_or(0),
+        })
+    }
+
+    /// Removes the cgroup once no processes are left in it, which blocks for as long as the ones
+    /// the task left behind keep running
+    pub(crate) fn remove(&self) {
+        let mut interval = EMPTY_POLL_INTERVAL;
+        // rmdir fails with EBUSY until then
+        while let Err(err) = fs::remove_dir(&self.path) {
+            if err.raw_os_error() != Some(libc::EBUSY) {
+                return;
+            }
+            thread::sleep(interval);
+            interval = (interval * 2).min(EMPTY_POLL_MAX_INTERVAL);
+        }
+    }
+
+    /// Removes the cgroup unless processes are still running in it
+    pub(crate) fn remove_if_empty(&self) {
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup_files() {
+        let mountinfo = "25 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+                         30 25 0:26 / /sys/fs/cgroup rw,nosuid shared:4 - cgroup2 cgroup2 rw\n";
+        assert_eq!(
+            parse_mountinfo(mountinfo),
+            Some(("/".into(), "/sys/fs/cgroup".into()))
+        );
+        assert_eq!(
+            parse_mountinfo("25 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n"),
+            None
+        );
+        assert_eq!(
+            parse_proc_cgroup("4:memory:/user.slice\n0::/user.slice/user-1000.slice\n"),
+            Some("/user.slice/user-1000.slice".into())
+        );
+        assert_eq!(parse_proc_cgroup("4:memory:/user.slice\n"), None);
+
+        let memory_events = "low 0\nhigh 0\nmax 12\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(parse_oom_kills(memory_events), Some(1));
+        let usage = CgroupUsage {
+            memory_peak: None,
+            oom_kills: 1,
+        };
+        assert_eq!(
+            usage.exceeded(Signal::SIGKILL),
+            Some("out of memory".into())
+        );
+        assert_eq!(usage.exceeded(Signal::SIGTERM), None);
+        assert_eq!(CgroupUsage::default().exceeded(Signal::SIGKILL), None);
+    }
+}
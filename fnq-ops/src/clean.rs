@@ -0,0 +1,72 @@
+use std::{ffi, fs, io, path};
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::{compress, meta, IOResultExt, OpsError};
+
+/// Subdirectory of the queue dir that archived queue files are moved into
+pub const ARCHIVE_DIR: &str = "archive";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum CleanPolicy {
+    #[default]
+    Never,
+    Success,
+    Failure,
+    Always,
+}
+
+impl CleanPolicy {
+    pub fn parse(arg: &str) -> Option<Self> {
+        match arg {
+            "never" => Some(CleanPolicy::Never),
+            "success" => Some(CleanPolicy::Success),
+            "failure" => Some(CleanPolicy::Failure),
+            "always" => Some(CleanPolicy::Always),
+            _ => None,
+        }
+    }
+
+    pub fn should_clean(&self, succeeded: bool) -> bool {
+        match self {
+            CleanPolicy::Never => false,
+            CleanPolicy::Success => succeeded,
+            CleanPolicy::Failure => !succeeded,
+            CleanPolicy::Always => true,
+        }
+    }
+}
+
+/// Removes a finished queue file, or if `archive` is set, moves it gzipped into `ARCHIVE_DIR`
+pub fn remove(
+    queue_dir: &path::Path,
+    queue_path: &path::Path,
+    archive: bool,
+) -> Result<(), OpsError> {
+    if archive {
+        let archive_dir = queue_dir.join(ARCHIVE_DIR);
+        if !archive_dir.is_dir() {
+            fs::create_dir(&archive_dir).path_context("create", &archive_dir)?;
+        }
+
+        let mut archive_name = queue_path
+            .file_name()
+            .ok_or_else(|| OpsError::FileNotFound(queue_path.into()))?
+            .to_os_string();
+        archive_name.push(ffi::OsStr::new(".gz"));
+
+        let archive_path = archive_dir.join(archive_name);
+        let mut queue_file = compress::open(queue_path).path_context("open", queue_path)?;
+        let mut encoder = GzEncoder::new(
+            fs::File::create(&archive_path).path_context("create", &archive_path)?,
+            Compression::default(),
+        );
+        io::copy(&mut queue_file, &mut encoder)
+            .and_then(|_| encoder.finish()?.sync_all())
+            .path_context("archive", queue_path)?;
+    }
+
+    fs::remove_file(queue_path).path_context("remove", queue_path)?;
+    meta::remove(queue_dir, queue_path).path_context("remove metadata for", queue_path)?;
+    Ok(())
+}
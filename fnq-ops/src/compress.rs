@@ -0,0 +1,73 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::prelude::*;
+use std::{ffi, fs, io, path};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Determines if a queue file was gzipped after its task finished. Leaves the file rewound
+pub fn is_compressed(file: &mut fs::File) -> Result<bool, io::Error> {
+    let mut magic = [0; 2];
+    file.seek(SeekFrom::Start(0))?;
+    let compressed = match file.read_exact(&mut magic) {
+        Ok(_) => magic == GZIP_MAGIC,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(err) => return Err(err),
+    };
+    file.seek(SeekFrom::Start(0))?;
+    Ok(compressed)
+}
+
+/// Wraps a queue file in a reader that transparently decompresses it if needed
+pub fn reader(mut file: fs::File) -> Result<Box<dyn Read + Send>, io::Error> {
+    if is_compressed(&mut file)? {
+        Ok(Box::new(MultiGzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Opens a queue file for reading, decompressing it if needed
+pub fn open(path: &path::Path) -> Result<Box<dyn Read + Send>, io::Error> {
+    reader(fs::File::open(path)?)
+}
+
+/// Gzips a finished queue file. The compressed bytes are written back into the same file rather
+/// than renamed over it, as a new file would get a new birth time and with it a new place at the
+/// end of the queue
+pub fn compress_in_place(path: &path::Path) -> Result<(), io::Error> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    if is_compressed(&mut file)? {
+        return Ok(());
+    }
+
+    // The temporary file must not start with the queue file prefix so it never shows up as a task
+    let mut tmp_name = ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".gz.tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let tmp_file = fs::OpenOptions::new()
+        .create_new(true)
+        .read(true)
+        .write(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    let mut encoder = GzEncoder::new(tmp_file, Compression::default());
+    let mut tmp_file = match io::copy(&mut file, &mut encoder).and_then(|_| encoder.finish()) {
+        Ok(tmp_file) => tmp_file,
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    };
+
+    // Should this fail halfway, the compressed copy is left behind so the output isn't lost
+    tmp_file.seek(SeekFrom::Start(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    let len = io::copy(&mut tmp_file, &mut file)?;
+    file.set_len(len)?;
+    file.sync_all()?;
+    fs::remove_file(&tmp_path)
+}
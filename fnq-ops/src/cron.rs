@@ -0,0 +1,184 @@
+use std::{fmt, mem, time};
+
+use nix::libc;
+
+// Searching further than this for a matching minute means the expression can never match, e.g.
+// `0 0 30 2 *`. Covers the 29th of February at least once
+const SEARCH_YEARS: u64 = 5;
+
+/// A five field cron expression: minute, hour, day of month, month and day of week, matched
+/// against local time. Fields take `*`, numbers, ranges like `1-5`, steps like `*/15` or `0-30/10`
+/// and comma separated lists of those. `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`
+/// are shorthands
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // As in cron, a day matches either field if both are restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+/// Bitset of the values `field` allows, between `min` and `max`
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step| step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse().ok()?, end.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            // `5/10` runs from 5 to the end of the field like `5-59/10`
+            (value, if part.contains('/') { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Some(bits)
+}
+
+fn local_time(secs: u64) -> libc::tm {
+    let secs = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    tm
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Option<Self> {
+        let fields = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            fields => fields,
+        };
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & 1 << 7 != 0 {
+            weekdays |= 1;
+        }
+        let cron = Self {
+            expr: expr.trim().to_string(),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        };
+        cron.next_after(time::SystemTime::now())?;
+        Some(cron)
+    }
+
+    fn day_matches(&self, tm: &libc::tm) -> bool {
+        let day = self.days & 1 << tm.tm_mday != 0;
+        let weekday = self.weekdays & 1 << tm.tm_wday != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first whole minute after `after` that the expression matches, or `None` if it never
+    /// does
+    pub fn next_after(&self, after: time::SystemTime) -> Option<time::SystemTime> {
+        let after = after.duration_since(time::UNIX_EPOCH).ok()?.as_secs();
+        let mut minute = after - after % 60 + 60;
+        let search_end = minute + SEARCH_YEARS * 366 * 24 * 60 * 60;
+
+        while minute < search_end {
+            let tm = local_time(minute);
+            if self.months & 1 << (tm.tm_mon + 1) == 0
+                || !self.day_matches(&tm)
+                || self.hours & 1 << tm.tm_hour == 0
+            {
+                // Skips to the next hour, which is as far as DST changes are safe to skip
+                minute += (60 - tm.tm_min as u64) * 60;
+            } else if self.minutes & 1 << tm.tm_min == 0 {
+                minute += 60;
+            } else {
+                return Some(time::UNIX_EPOCH + time::Duration::from_secs(minute));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cron() {
+        assert_eq!(
+            parse_field("*/15", 0, 59),
+            Some(1 | 1 << 15 | 1 << 30 | 1 << 45)
+        );
+        assert_eq!(
+            parse_field("1-3,10", 0, 59),
+            Some(1 << 1 | 1 << 2 | 1 << 3 | 1 << 10)
+        );
+        assert_eq!(parse_field("50/5", 0, 59), Some(1 << 50 | 1 << 55));
+        assert_eq!(parse_field("60", 0, 59), None);
+        assert_eq!(parse_field("5-1", 0, 59), None);
+        assert_eq!(parse_field("*/0", 0, 59), None);
+
+        assert!(Cron::parse("*/5 * * * *").is_some());
+        assert!(Cron::parse("0 9 * * 1-5").is_some());
+        assert!(Cron::parse("@daily").is_some());
+        assert!(Cron::parse("0 9 * *").is_none());
+        assert!(Cron::parse("0 0 30 2 *").is_none());
+        assert_eq!(
+            Cron::parse("0 0 * * 7").unwrap().weekdays,
+            Cron::parse("0 0 * * 0,7").unwrap().weekdays
+        );
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let every_5 = Cron::parse("*/5 * * * *").unwrap();
+        let start = time::UNIX_EPOCH + time::Duration::from_secs(1_700_000_000);
+        let next = every_5.next_after(start).unwrap();
+        let secs = next.duration_since(time::UNIX_EPOCH).unwrap().as_secs();
+        assert_eq!(secs % 300, 0);
+        assert!(next > start && next <= start + time::Duration::from_secs(300));
+        assert_eq!(
+            every_5.next_after(next),
+            Some(next + time::Duration::from_secs(300))
+        );
+
+        let hourly = Cron::parse("@hourly").unwrap();
+        let next = hourly.next_after(start).unwrap();
+        assert_eq!(
+            local_time(next.duration_since(time::UNIX_EPOCH).unwrap().as_secs()).tm_min,
+            0
+        );
+    }
+}
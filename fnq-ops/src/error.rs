@@ -0,0 +1,96 @@
+use std::{error, ffi, fmt, io, path, time};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum OpsError {
+    StringConv,
+    QueueEmpty,
+    FileNotFound(ffi::OsString),
+    /// A job name or prefix that more than one job matches
+    AmbiguousJob(ffi::OsString),
+    ScheduleNotFound(ffi::OsString),
+    CommandNotFound(ffi::OsString),
+    NotExecutable(ffi::OsString),
+    JobFailed(path::PathBuf, String),
+    /// Needs a running or waiting queue file, e.g. to kill it
+    JobNotRunning(path::PathBuf),
+    /// Needs a finished queue file, e.g. to remove it
+    JobRunning(path::PathBuf),
+    IO(io::Error),
+    /// IO error with the operation and path it happened on
+    PathIO(&'static str, path::PathBuf, io::Error),
+    Lock(path::PathBuf, String),
+    Unix(String),
+    SystemTime(time::SystemTimeError),
+    Watcher(notify::Error),
+    WatcherUnknown(String),
+    Unknown(String),
+}
+
+impl fmt::Display for OpsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpsError::StringConv => write!(f, "UTF-8 conversion unsuccessful"),
+            OpsError::FileNotFound(filename) => write!(f, "Could not find queue file: {:?}", filename),
+            OpsError::AmbiguousJob(reference) => write!(f, "More than one job matches {:?}", reference),
+            OpsError::ScheduleNotFound(id) => write!(f, "Could not find schedule: {:?}", id),
+            OpsError::CommandNotFound(cmd) => write!(f, "Could not find command: {:?}", cmd),
+            OpsError::NotExecutable(cmd) => write!(f, "Command is not executable: {:?}", cmd),
+            OpsError::QueueEmpty => write!(f, "Lock file queue is empty"),
+            OpsError::JobFailed(path, status) => write!(f, "{:?} did not succeed: {}", path, status),
+            OpsError::JobNotRunning(path) => write!(f, "{:?} is not running or waiting to run", path),
+            OpsError::JobRunning(path) => write!(f, "{:?} is still running or waiting to run", path),
+            OpsError::IO(io_err) => io_err.fmt(f),
+            OpsError::PathIO(op, path, io_err) => write!(f, "Could not {} {:?}: {}", op, path, io_err),
+            OpsError::Lock(path, nix_err) => write!(f, "Could not lock {:?}: {}", path, nix_err),
+            OpsError::Unix(nix_err) => nix_err.fmt(f),
+            OpsError::SystemTime(sys_time_err) => sys_time_err.fmt(f),
+            OpsError::Watcher(notify_err) => notify_err.fmt(f),
+            OpsError::WatcherUnknown(str_err) => write!(f, "Unknown error(watcher): {}", str_err),
+            OpsError::Unknown(str_err) => write!(f, "Unknown error: {}", str_err),
+        }
+    }
+}
+
+impl error::Error for OpsError {}
+
+impl From<io::Error> for OpsError {
+    fn from(err: io::Error) -> Self {
+        OpsError::IO(err)
+    }
+}
+
+impl From<nix::Error> for OpsError {
+    fn from(err: nix::Error) -> Self {
+        OpsError::Unix(err.to_string())
+    }
+}
+
+impl From<time::SystemTimeError> for OpsError {
+    fn from(err: time::SystemTimeError) -> Self {
+        OpsError::SystemTime(err)
+    }
+}
+
+impl From<ffi::NulError> for OpsError {
+    fn from(_: ffi::NulError) -> Self {
+        OpsError::StringConv
+    }
+}
+
+impl From<notify::Error> for OpsError {
+    fn from(err: notify::Error) -> Self {
+        OpsError::Watcher(err)
+    }
+}
+
+pub trait IOResultExt<T> {
+    /// Adds the operation and path to an IO error so it can be told apart from others
+    fn path_context(self, op: &'static str, path: &path::Path) -> Result<T, OpsError>;
+}
+
+impl<T> IOResultExt<T> for Result<T, io::Error> {
+    fn path_context(self, op: &'static str, path: &path::Path) -> Result<T, OpsError> {
+        self.map_err(|err| OpsError::PathIO(op, path.into(), err))
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::{HashMap, VecDeque};
+use std::os::unix::prelude::*;
+use std::sync::mpsc;
+use std::{ffi, fmt, fs, path, time};
+
+use notify::{raw_watcher, Op, RawEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{files, status, OpsError, QUEUE_FILE_PREFIX};
+
+#[derive(Debug, PartialEq)]
+pub enum EventKind {
+    Enqueued,
+    /// The task's turn in the queue has come
+    Started,
+    /// The task wrote to its queue file. Writes are coalesced, so this is a notification rather
+    /// than one event per write
+    OutputAppended,
+    Exited(status::JobStatus),
+    /// The queue file was removed, e.g. by --clean or --gc
+    Removed,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::Enqueued => write!(f, "enqueued"),
+            EventKind::Started => write!(f, "started"),
+            EventKind::OutputAppended => write!(f, "output"),
+            EventKind::Exited(job_status) => write!(f, "exited\t{}", job_status),
+            EventKind::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    /// Queue file name of the job
+    pub id: ffi::OsString,
+    pub kind: EventKind,
+    /// When the change was seen
+    pub time: time::SystemTime,
+}
+
+// What has been reported for a queue file so far
+#[derive(Default)]
+struct Tracked {
+    len: u64,
+    started: bool,
+    status: Option<status::JobStatus>,
+}
+
+impl Tracked {
+    fn read(queue_path: &path::Path) -> Result<Option<Self>, OpsError> {
+        let mut queue_file = match fs::File::open(queue_path) {
+            Ok(queue_file) => queue_file,
+            // Gone by the time the event was handled
+            Err(_) => return Ok(None),
+        };
+        Ok(Some(Self {
+            len: queue_file.metadata()?.len(),
+            started: status::read_header(queue_file.try_clone()?)?.started,
+            status: status::read_status(&mut queue_file)?,
+        }))
+    }
+}
+
+/// Lifecycle events of the jobs in a queue dir, in the order they were seen. Only reports changes
+/// made after it was created; see `list` for the jobs already there
+pub struct Events {
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    rx: mpsc::Receiver<RawEvent>,
+    queue_dir: path::PathBuf,
+    tracked: HashMap<ffi::OsString, Tracked>,
+    pending: VecDeque<Event>,
+}
+
+impl Events {
+    pub fn new(queue_dir: &path::PathBuf) -> Result<Self, OpsError> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = raw_watcher(tx)?;
+        watcher.watch(queue_dir, RecursiveMode::NonRecursive)?;
+
+        // Watching first so nothing queued in between is missed
+        let mut tracked = HashMap::new();
+        for entry in files::files(queue_dir)? {
+            if let Some(state) = Tracked::read(&entry.filepath)? {
+                tracked.insert(entry.filepath.file_name().unwrap_or_default().into(), state);
+            }
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            rx,
+            queue_dir: queue_dir.clone(),
+            tracked,
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn push(&mut self, id: &ffi::OsStr, kind: EventKind) {
+        self.pending.push_back(Event {
+            id: id.into(),
+            kind,
+            time: time::SystemTime::now(),
+        });
+    }
+
+    fn handle(&mut self, raw_event: RawEvent) -> Result<(), OpsError> {
+        let op = match raw_event.op {
+            Ok(op) => op,
+            Err(err) => return Err(OpsError::Watcher(err)),
+        };
+        let id = match raw_event.path.as_ref().and_then(|path| path.file_name()) {
+            // Also skips the meta and archive dirs and temporary files of --compress
+            Some(id) if id.as_bytes().starts_with(QUEUE_FILE_PREFIX.as_bytes()) => id.to_owned(),
+            _ => return Ok(()),
+        };
+        let queue_path = self.queue_dir.join(&id);
+
+        if op.contains(Op::CREATE) && !self.tracked.contains_key(&id) {
+            self.tracked.insert(id.clone(), Tracked::default());
+            self.push(&id, EventKind::Enqueued);
+        }
+
+        if op.intersects(Op::WRITE | Op::CLOSE_WRITE) {
+            if let Some(current) = Tracked::read(&queue_path)? {
+                let seen = self.tracked.entry(id.clone()).or_default();
+                let mut kinds = Vec::new();
+                if seen.status.is_none() {
+                    if current.started && !seen.started {
+                        kinds.push(EventKind::Started);
+                    }
+                    if let Some(job_status) = &current.status {
+                        kinds.push(EventKind::Exited(job_status.clone()));
+                    } else if current.started && seen.started && current.len > seen.len {
+                        kinds.push(EventKind::OutputAppended);
+                    }
+                }
+                *seen = current;
+                for kind in kinds {
+                    self.push(&id, kind);
+                }
+            }
+        }
+
+        // --compress renames its gzipped copy over the queue file, which isn't a removal
+        if op.intersects(Op::REMOVE | Op::RENAME)
+            && !queue_path.exists()
+            && self.tracked.remove(&id).is_some()
+        {
+            self.push(&id, EventKind::Removed);
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for Events {
+    type Item = Result<Event, OpsError>;
+
+    /// Blocks until the next event
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let raw_event = self.rx.recv().ok()?;
+            if let Err(err) = self.handle(raw_event) {
+                return Some(Err(err));
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
@@ -0,0 +1,75 @@
+use std::{ffi, fs, io, path, time};
+
+use crate::os_strings::OsStringStartsWithExt;
+use crate::{IOResultExt, OpsError, QUEUE_FILE_PREFIX};
+
+pub struct QueueEntry {
+    pub filepath: path::PathBuf,
+    created: time::SystemTime,
+}
+
+pub fn files(queue_dir: &path::PathBuf) -> Result<Vec<QueueEntry>, OpsError> {
+    let file_path_prefix = concat_os_strings!(
+        queue_dir,
+        ffi::OsString::from("/"),
+        ffi::OsString::from(QUEUE_FILE_PREFIX)
+    );
+
+    let mut queue_files = fs::read_dir(queue_dir)
+        .path_context("read queue dir", queue_dir)?
+        .filter(|dir_entry| {
+            if let Ok(dir_entry) = dir_entry {
+                let filepath = dir_entry.path();
+                return filepath.is_file()
+                    && filepath
+                        .as_os_str()
+                        .starts_with(file_path_prefix.as_os_str());
+            }
+            false
+        })
+        .filter_map(|dir_entry| {
+            let entry = dir_entry.and_then(|dir_entry| {
+                Ok(QueueEntry {
+                    filepath: dir_entry.path(),
+                    created: dir_entry.metadata()?.created()?,
+                })
+            });
+            match entry {
+                // Removed since the dir was read, e.g. by --clean
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                entry => Some(entry),
+            }
+        })
+        .collect::<Result<Vec<QueueEntry>, _>>()
+        .path_context("read queue dir", queue_dir)?;
+
+    queue_files.sort_by_key(|file| file.created);
+
+    Ok(queue_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_files_in_queue_order() {
+        let queue_dir = std::env::temp_dir().join(format!("fnq-files-test-{}", std::process::id()));
+        fs::create_dir_all(&queue_dir).unwrap();
+        // Queued first, but sorts after the second one by name
+        for filename in ["fnq999.2", "fnq1000.1"] {
+            fs::write(queue_dir.join(filename), "").unwrap();
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        fs::write(queue_dir.join("other"), "").unwrap();
+
+        let filenames: Vec<_> = files(&queue_dir)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.filepath.file_name().unwrap().to_os_string())
+            .collect();
+        fs::remove_dir_all(&queue_dir).unwrap();
+        assert_eq!(filenames, ["fnq999.2", "fnq1000.1"]);
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::Read;
+use std::{io, path, thread, time};
+
+use crate::wait::StatusWaiter;
+use crate::{compress, open_file, OpsError};
+
+/// How long to wait for more output before reading the queue file again
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// Reads a queue file as it is being written, like `tail -f`. Reaches EOF once the task has
+/// finished and its status line has been read
+pub struct Follow {
+    queue_file: Box<dyn Read + Send>,
+    waiter: StatusWaiter,
+    finished: bool,
+}
+
+impl Follow {
+    pub fn open(queue_path: &path::PathBuf) -> Result<Self, OpsError> {
+        let mut queue_file = open_file(queue_path)?;
+        // Only finished queue files are compressed, so there is nothing left to wait for
+        let finished = compress::is_compressed(&mut queue_file)?;
+        Ok(Self {
+            queue_file: compress::reader(queue_file)?,
+            waiter: StatusWaiter::new(queue_path.clone()),
+            finished,
+        })
+    }
+
+    /// Reads whatever output is available without waiting for more. Returns `None` if there is
+    /// none yet but the task hasn't finished
+    pub fn try_read(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let len = self.queue_file.read(buf)?;
+        if len > 0 || self.finished {
+            return Ok(Some(len));
+        }
+
+        if self
+            .waiter
+            .poll_status()
+            .map_err(io::Error::other)?
+            .is_ready()
+        {
+            // Reads whatever was written since the last read one more time
+            self.finished = true;
+            return self.queue_file.read(buf).map(Some);
+        }
+        Ok(None)
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(len) = self.try_read(buf)? {
+                return Ok(len);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Copies a queue file to stdout as it is being written, until its task has finished
+pub fn tail(queue_path: &path::PathBuf) -> Result<(), OpsError> {
+    io::copy(&mut Follow::open(queue_path)?, &mut io::stdout())?;
+    Ok(())
+}
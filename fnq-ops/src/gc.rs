@@ -0,0 +1,76 @@
+use std::os::unix::prelude::*;
+use std::{io, path, time};
+
+use nix::fcntl;
+
+use crate::{clean, files, is_locked, open_file, status, OpsError};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct GcPolicy {
+    /// Only removes queue files that finished longer than this ago
+    pub older_than: Option<time::Duration>,
+    /// Always keeps this many of the most recent removable queue files
+    pub keep_last: Option<usize>,
+    /// Only removes queue files whose task exited with status 0
+    pub only_succeeded: bool,
+    /// Moves removed queue files gzipped into the archive dir instead of deleting them
+    pub archive: bool,
+}
+
+/// Removes finished queue files from `queue_dir` according to `policy` and returns the removed
+/// paths. Files that are still locked (running or waiting to run) or that have no status line are
+/// never touched
+pub fn gc(queue_dir: &path::PathBuf, policy: &GcPolicy) -> Result<Vec<path::PathBuf>, OpsError> {
+    let mut candidates = Vec::new();
+
+    for entry in files::files(queue_dir)? {
+        let mut opened_file = match open_file(&entry.filepath) {
+            // Removed by someone else since it was listed
+            Err(OpsError::PathIO(_, _, err)) if err.kind() == io::ErrorKind::NotFound => continue,
+            result => result?,
+        };
+        if is_locked(opened_file.as_raw_fd())? {
+            continue;
+        }
+
+        match status::read_status(&mut opened_file)? {
+            None => continue,
+            Some(status) if policy.only_succeeded && !status.succeeded() => continue,
+            Some(_) => candidates.push((entry, opened_file)),
+        }
+    }
+
+    let removable = candidates
+        .len()
+        .saturating_sub(policy.keep_last.unwrap_or(0));
+    let now = time::SystemTime::now();
+    let mut removed = Vec::new();
+
+    for (entry, opened_file) in candidates.into_iter().take(removable) {
+        if let Some(older_than) = policy.older_than {
+            let finished = opened_file.metadata()?.modified()?;
+            if now.duration_since(finished).unwrap_or_default() < older_than {
+                continue;
+            }
+        }
+
+        // Holding the lock while removing keeps anyone else from treating it as a live task
+        if fcntl::flock(
+            opened_file.as_raw_fd(),
+            fcntl::FlockArg::LockExclusiveNonblock,
+        )
+        .is_err()
+        {
+            continue;
+        }
+
+        match clean::remove(queue_dir, &entry.filepath, policy.archive) {
+            // Someone else removed it first, e.g. its own --clean or another gc
+            Err(OpsError::PathIO(_, _, err)) if err.kind() == io::ErrorKind::NotFound => continue,
+            result => result?,
+        }
+        removed.push(entry.filepath);
+    }
+
+    Ok(removed)
+}
@@ -0,0 +1,88 @@
+use std::{ffi, fmt, io, path, process, time};
+
+use crate::OpsError;
+
+/// Subdirectory of the queue dir holding the `on-start`, `on-success` and `on-failure` hooks
+pub const HOOKS_DIR: &str = "hooks";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    /// Once the task's turn in the queue has come and it was executed
+    Start,
+    Success,
+    Failure,
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hook::Start => write!(f, "on-start"),
+            Hook::Success => write!(f, "on-success"),
+            Hook::Failure => write!(f, "on-failure"),
+        }
+    }
+}
+
+/// What a hook gets to know about the task, handed to it as environment variables
+#[derive(Debug, Default)]
+pub struct HookEnv {
+    pub job_id: ffi::OsString,
+    pub queue_file: path::PathBuf,
+    /// Exit code as a shell would report it, i.e. 128 plus the signal for killed tasks
+    pub exit_code: Option<i32>,
+    pub duration: Option<time::Duration>,
+}
+
+fn run_command(
+    mut command: process::Command,
+    hook: Hook,
+    queue_dir: &path::Path,
+    env: &HookEnv,
+) -> Result<(), OpsError> {
+    command
+        .env("FNQJOBID", &env.job_id)
+        .env("FNQ_QUEUE_FILE", &env.queue_file)
+        .env("FNQ_HOOK", hook.to_string())
+        // So that a hook queueing a follow-up task queues it in the same queue
+        .env("FNQ_DIR", queue_dir.canonicalize()?)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null());
+    if let Some(exit_code) = env.exit_code {
+        command.env("FNQ_EXIT_CODE", exit_code.to_string());
+    }
+    if let Some(duration) = env.duration {
+        command.env("FNQ_DURATION", format!("{:.3}", duration.as_secs_f64()));
+    }
+
+    let exit_status = command.status()?;
+    if !exit_status.success() {
+        return Err(io::Error::other(exit_status.to_string()).into());
+    }
+    Ok(())
+}
+
+/// Runs the `hook` script in the queue dir's hooks dir, if there is one, and then `command` (from
+/// `--on-success` or `--on-failure`) with `sh -c`. Waits for both to finish, and returns the
+/// first error if either failed
+pub fn run(
+    queue_dir: &path::Path,
+    hook: Hook,
+    command: Option<&ffi::OsStr>,
+    env: &HookEnv,
+) -> Result<(), OpsError> {
+    let mut result = Ok(());
+
+    let hook_path = queue_dir.join(HOOKS_DIR).join(hook.to_string());
+    if hook_path.is_file() {
+        result = run_command(process::Command::new(hook_path), hook, queue_dir, env);
+    }
+
+    if let Some(command) = command {
+        let mut sh = process::Command::new("sh");
+        sh.arg("-c").arg(command);
+        result = result.and(run_command(sh, hook, queue_dir, env));
+    }
+
+    result
+}
@@ -0,0 +1,32 @@
+use std::os::unix::prelude::*;
+use std::path;
+
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+
+use crate::{is_locked, open_file, OpsError};
+
+/// Sends `signal` to the tasks of running or waiting queue files. Started tasks run in a session
+/// of their own, so the signal goes to their whole process group
+pub fn kill(queue_paths: &[path::PathBuf], signal: Signal) -> Result<(), OpsError> {
+    for queue_path in queue_paths {
+        let opened_file = open_file(queue_path)?;
+        // Once it's finished, the pid in the file name may belong to some other process by now
+        if !is_locked(opened_file.as_raw_fd())? {
+            return Err(OpsError::JobNotRunning(queue_path.clone()));
+        }
+
+        let pid = queue_path
+            .extension()
+            .and_then(|pid| pid.to_str()?.parse().ok())
+            .map(Pid::from_raw)
+            .ok_or_else(|| OpsError::FileNotFound(queue_path.into()))?;
+        match signal::killpg(pid, signal) {
+            // Still waiting for its turn, so it isn't a process group leader yet
+            Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => signal::kill(pid, signal)?,
+            result => result?,
+        }
+    }
+
+    Ok(())
+}
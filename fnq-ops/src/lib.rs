@@ -0,0 +1,105 @@
+//! Queue operations behind the `fnq` binary and the `fnq` library. The types that the `fnq` crate
+//! re-exports are part of its API and only change with a release of it. Everything else is
+//! internal to fnq; use the `fnq` crate instead
+
+pub use cgroup::{cgroup_dir, CgroupLimits};
+pub use clean::CleanPolicy;
+pub use cron::Cron;
+pub use error::{IOResultExt, OpsError};
+pub use events::{Event, EventKind, Events};
+pub use follow::{tail, Follow};
+pub use gc::{gc, GcPolicy};
+pub use kill::kill;
+pub use limits::Limits;
+pub use list::{find, job, list, Job, JobId, JobState};
+pub use locks::{locks_dir, NamedLock};
+pub use on_change::on_change;
+pub use priority::{IoClass, IoPriority, NICE_RANGE};
+pub use queue::{queue, queue_barrier, DuplicatePolicy, QueueOptions};
+pub use repair::{repair, Repaired};
+pub use rm::rm;
+pub use schedule::{schedule, schedules, unschedule, Schedule, ScheduleEntry};
+pub use status::{JobHeader, JobStatus};
+pub use tap::{tap, tap_batch};
+pub use wait::{block, block_batch, wait_for_status};
+pub use watch::watch;
+pub use last::last;
+
+use nix::fcntl;
+use std::os::unix::prelude::*;
+use std::{fs, io, path};
+
+#[macro_use]
+mod os_strings;
+mod admission;
+mod barrier;
+mod cgroup;
+mod clean;
+mod compress;
+mod cron;
+mod error;
+mod events;
+mod files;
+mod follow;
+mod gc;
+mod hooks;
+mod kill;
+mod queue;
+mod tap;
+mod wait;
+mod watch;
+mod last;
+mod limits;
+mod list;
+mod locks;
+mod meta;
+mod on_change;
+mod priority;
+mod repair;
+mod rm;
+mod schedule;
+mod status;
+
+pub const QUEUE_FILE_PREFIX: &str = "fnq";
+
+pub(crate) fn open_file(path_buf: &path::PathBuf) -> Result<fs::File, OpsError> {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path_buf)
+        .path_context("open", path_buf)
+}
+
+pub(crate) fn is_locked(raw_fd: RawFd) -> Result<bool, OpsError> {
+    match fcntl::flock(raw_fd, fcntl::FlockArg::LockSharedNonblock) {
+        Ok(_) => {
+            fcntl::flock(raw_fd, fcntl::FlockArg::Unlock)?;
+            Ok(false)
+        }
+        Err(nix::Error::Sys(nix::errno::EWOULDBLOCK)) => Ok(true),
+        Err(err) => Err(OpsError::from(err)),
+    }
+}
+
+/// Blocks until the task of a queue file has finished. A queue file that is gone was removed
+/// after its task finished (e.g. by --clean or gc), so that returns right away
+pub(crate) fn block_on_locked_file(path_buf: &path::PathBuf) -> Result<(), OpsError> {
+    // File handler needs to be alive for the scope of file descriptor
+    let stay_alive = match open_file(path_buf) {
+        Err(OpsError::PathIO(_, _, err)) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        result => result?,
+    };
+    let fd: RawFd = stay_alive.as_raw_fd();
+    let lock_err = |err: nix::Error| OpsError::Lock(path_buf.clone(), err.to_string());
+    match fcntl::flock(fd, fcntl::FlockArg::LockSharedNonblock) {
+        Ok(_) => {
+            fcntl::flock(fd, fcntl::FlockArg::Unlock).map_err(lock_err)?;
+        }
+        Err(nix::Error::Sys(nix::errno::EWOULDBLOCK)) => {
+            fcntl::flock(fd, fcntl::FlockArg::LockShared).map_err(lock_err)?;
+            fcntl::flock(fd, fcntl::FlockArg::Unlock).map_err(lock_err)?;
+        }
+        Err(err) => return Err(lock_err(err)),
+    };
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::{ffi, mem, time};
+
+use nix::libc;
+use nix::sys::signal::Signal;
+
+use crate::queue::timeout_secs;
+use crate::OpsError;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// Limits set with `setrlimit` in the task's process right before it execs. They are inherited by
+/// everything the task spawns
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Limits {
+    /// `--limit-mem`: size of the address space in bytes (`RLIMIT_AS`). Going over it makes
+    /// allocations fail, which most programs exit or abort on
+    pub mem: Option<u64>,
+    /// `--limit-cpu`: CPU time, rounded up to whole seconds (`RLIMIT_CPU`). Going over it sends
+    /// SIGXCPU, and SIGKILL a second later
+    pub cpu: Option<time::Duration>,
+    /// `--limit-nofile`: one more than the highest file descriptor it can open (`RLIMIT_NOFILE`)
+    pub nofile: Option<u64>,
+    /// `--limit-core`: largest core dump in bytes (`RLIMIT_CORE`). 0 turns them off
+    pub core: Option<u64>,
+}
+
+struct Rlimit {
+    flag: &'static str,
+    resource: Resource,
+    soft: libc::rlim_t,
+    hard: libc::rlim_t,
+}
+
+fn hard_limit(resource: Resource) -> Result<libc::rlim_t, OpsError> {
+    let mut rlimit: libc::rlimit = unsafe { mem::zeroed() };
+    if unsafe { libc::getrlimit(resource, &mut rlimit) } != 0 {
+        return Err(OpsError::from(nix::Error::last()));
+    }
+    Ok(rlimit.rlim_max)
+}
+
+/// User and system CPU time in `usage`
+pub(crate) fn cpu_time(usage: &libc::rusage) -> time::Duration {
+    let timeval = |tv: libc::timeval| {
+        time::Duration::from_secs(tv.tv_sec as u64) + time::Duration::from_micros(tv.tv_usec as u64)
+    };
+    timeval(usage.ru_utime) + timeval(usage.ru_stime)
+}
+
+impl Limits {
+    /// The fnq flags that would parse back into these limits
+    pub fn to_args(&self) -> Vec<ffi::OsString> {
+        let mut args: Vec<ffi::OsString> = Vec::new();
+        let mut push = |flag: &str, value: String| {
+            args.push(flag.into());
+            args.push(value.into());
+        };
+        if let Some(mem) = self.mem {
+            push("--limit-mem", mem.to_string());
+        }
+        if let Some(cpu) = self.cpu {
+            push("--limit-cpu", format!("{}s", timeout_secs(cpu)));
+        }
+        if let Some(nofile) = self.nofile {
+            push("--limit-nofile", nofile.to_string());
+        }
+        if let Some(core) = self.core {
+            push("--limit-core", core.to_string());
+        }
+        args
+    }
+
+    fn rlimits(&self) -> Vec<Rlimit> {
+        let same = |flag, resource, value: u64| Rlimit {
+            flag,
+            resource,
+            soft: value as libc::rlim_t,
+            hard: value as libc::rlim_t,
+        };
+        let mut rlimits = Vec::new();
+        if let Some(mem) = self.mem {
+            rlimits.push(same("--limit-mem", libc::RLIMIT_AS, mem));
+        }
+        if let Some(cpu) = self.cpu {
+            let secs = timeout_secs(cpu) as libc::rlim_t;
+            // With the hard limit a second above the soft one, SIGXCPU comes first and can be
+            // told apart from any other SIGKILL
+            rlimits.push(Rlimit {
+                flag: "--limit-cpu",
+                resource: libc::RLIMIT_CPU,
+                soft: secs,
+                hard: secs.saturating_add(1),
+            });
+        }
+        if let Some(nofile) = self.nofile {
+            rlimits.push(same("--limit-nofile", libc::RLIMIT_NOFILE, nofile));
+        }
+        if let Some(core) = self.core {
+            rlimits.push(same("--limit-core", libc::RLIMIT_CORE, core));
+        }
+        rlimits
+    }
+
+    /// Fails if a limit is above the hard limit fnq runs with, which only root could raise, so
+    /// that it fails when queueing rather than once the task's turn has come
+    pub(crate) fn check(&self) -> Result<(), OpsError> {
+        for rlimit in self.rlimits() {
+            let hard = hard_limit(rlimit.resource)?;
+            if rlimit.soft > hard {
+                return Err(OpsError::Unknown(format!(
+                    "{} is above the hard limit of {}",
+                    rlimit.flag, hard
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Lowers the limits of the calling process
+    pub(crate) fn apply(&self) -> Result<(), OpsError> {
+        for limit in self.rlimits() {
+            let rlimit = libc::rlimit {
+                rlim_cur: limit.soft,
+                rlim_max: limit.hard.min(hard_limit(limit.resource)?),
+            };
+            if unsafe { libc::setrlimit(limit.resource, &rlimit) } != 0 {
+                return Err(OpsError::from(nix::Error::last()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Which limit the task went over, if that is why it was killed by `signal`, given the CPU
+    /// time `wait4` reported for it
+    pub(crate) fn exceeded(&self, signal: Signal, cpu_time: time::Duration) -> Option<String> {
+        let cpu = self.cpu?;
+        let secs = timeout_secs(cpu);
+        let over_cpu = match signal {
+            Signal::SIGXCPU => true,
+            Signal::SIGKILL => cpu_time >= time::Duration::from_secs(secs),
+            _ => false,
+        };
+        if over_cpu {
+            Some(format!("exceeded --limit-cpu {}s", secs))
+        } else {
+            None
+        }
+    }
+}
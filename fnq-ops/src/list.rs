@@ -0,0 +1,212 @@
+use std::os::unix::prelude::*;
+use std::{ffi, fmt, path};
+
+use crate::{files, is_locked, meta, open_file, status, IoPriority, OpsError};
+
+#[derive(Debug, PartialEq)]
+pub enum JobState {
+    /// Queued behind other tasks
+    Waiting,
+    Running,
+    Finished(status::JobStatus),
+    /// Not running but without a status line, e.g. because its supervisor died
+    Unknown,
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobState::Waiting => write!(f, "waiting"),
+            JobState::Running => write!(f, "running"),
+            JobState::Finished(job_status) => job_status.fmt(f),
+            JobState::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub filepath: path::PathBuf,
+    pub state: JobState,
+    pub header: status::JobHeader,
+    /// From `--name`
+    pub name: Option<ffi::OsString>,
+    /// From `--label`, in the order given
+    pub labels: Vec<(ffi::OsString, ffi::OsString)>,
+    /// From `--batch`
+    pub batch: Option<ffi::OsString>,
+    /// From `--nice`
+    pub nice: Option<i32>,
+    /// From `--ionice`
+    pub ionice: Option<IoPriority>,
+}
+
+impl Job {
+    /// Whether the job has every one of `labels`
+    pub fn has_labels(&self, labels: &[(ffi::OsString, ffi::OsString)]) -> bool {
+        labels.iter().all(|label| self.labels.contains(label))
+    }
+
+    pub fn id(&self) -> JobId {
+        JobId(self.filepath.file_name().unwrap_or_default().into())
+    }
+}
+
+/// Name of a task's queue file, e.g. `fnq1612345678901.4242`. Also handed to the task as
+/// `$FNQJOBID`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JobId(ffi::OsString);
+
+impl JobId {
+    pub fn as_os_str(&self) -> &ffi::OsStr {
+        &self.0
+    }
+
+    /// Pid of the task, which is the part of the queue file name after the `.`
+    pub fn pid(&self) -> Option<u32> {
+        let (_, pid) = self.0.to_str()?.rsplit_once('.')?;
+        pid.parse().ok()
+    }
+}
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.to_string_lossy().fmt(f)
+    }
+}
+
+impl From<ffi::OsString> for JobId {
+    fn from(filename: ffi::OsString) -> Self {
+        Self(filename)
+    }
+}
+
+impl From<&str> for JobId {
+    fn from(filename: &str) -> Self {
+        Self(filename.into())
+    }
+}
+
+// Jobs queued by older versions of fnq have no metadata, and so no name or labels either
+fn read_meta(filepath: &path::Path) -> Option<meta::JobMeta> {
+    let queue_dir = filepath.parent()?;
+    meta::read(queue_dir, filepath).ok().flatten()
+}
+
+/// Reads the state, header, name and labels of a single queue file
+pub fn job(filepath: path::PathBuf) -> Result<Job, OpsError> {
+    let mut opened_file = open_file(&filepath)?;
+    let header = status::read_header(opened_file.try_clone()?)?;
+    let state = if is_locked(opened_file.as_raw_fd())? {
+        if header.started {
+            JobState::Running
+        } else {
+            JobState::Waiting
+        }
+    } else {
+        // Checked before the status, as the supervisor only lets go once it wrote that
+        let supervised = match filepath.parent() {
+            Some(queue_dir) => meta::is_locked(queue_dir, &filepath)?,
+            None => false,
+        };
+        match status::read_status(&mut opened_file)? {
+            Some(job_status) => JobState::Finished(job_status),
+            None if supervised => JobState::Running,
+            None => JobState::Unknown,
+        }
+    };
+
+    let job_meta = read_meta(&filepath).unwrap_or_default();
+    Ok(Job {
+        filepath,
+        state,
+        header,
+        name: job_meta.name,
+        labels: job_meta.labels,
+        batch: job_meta.batch,
+        nice: job_meta.nice,
+        ionice: job_meta.ionice,
+    })
+}
+
+/// Queue files of the jobs queued with `--batch <batch>`, in queue order
+pub fn batch_files(
+    queue_dir: &path::PathBuf,
+    batch: &ffi::OsStr,
+) -> Result<Vec<path::PathBuf>, OpsError> {
+    Ok(files::files(queue_dir)?
+        .into_iter()
+        .map(|entry| entry.filepath)
+        .filter(|filepath| {
+            read_meta(filepath)
+                .and_then(|job_meta| job_meta.batch)
+                .as_deref()
+                == Some(batch)
+        })
+        .collect())
+}
+
+/// Finds the queue file of the job that `reference` refers to: its queue file name, its name (the
+/// most recent job if several share it), or else a prefix of either that only one job matches.
+/// Only ever finds queue files in `queue_dir`, so references that are paths are never found
+pub fn find(queue_dir: &path::PathBuf, reference: &ffi::OsStr) -> Result<path::PathBuf, OpsError> {
+    let not_found = || OpsError::FileNotFound(reference.into());
+    if reference.is_empty() || reference.as_bytes().contains(&b'/') {
+        return Err(not_found());
+    }
+
+    let named: Vec<(path::PathBuf, Option<ffi::OsString>)> = files::files(queue_dir)?
+        .into_iter()
+        .map(|entry| {
+            let name = read_meta(&entry.filepath).and_then(|job_meta| job_meta.name);
+            (entry.filepath, name)
+        })
+        .collect();
+
+    if let Some((filepath, _)) = named
+        .iter()
+        .find(|(filepath, _)| filepath.file_name() == Some(reference))
+    {
+        return Ok(filepath.clone());
+    }
+    if let Some((filepath, _)) = named
+        .iter()
+        .rev()
+        .find(|(_, name)| name.as_deref() == Some(reference))
+    {
+        return Ok(filepath.clone());
+    }
+
+    let is_prefix = |candidate: &ffi::OsStr| candidate.as_bytes().starts_with(reference.as_bytes());
+    let mut matches = named.into_iter().filter(|(filepath, name)| {
+        is_prefix(filepath.file_name().unwrap_or_default())
+            || name.as_deref().is_some_and(is_prefix)
+    });
+    match (matches.next(), matches.next()) {
+        (Some((filepath, _)), None) => Ok(filepath),
+        (Some(_), Some(_)) => Err(OpsError::AmbiguousJob(reference.into())),
+        (None, _) => Err(not_found()),
+    }
+}
+
+/// Lists every queue file in `queue_dir` in queue order. Lost ones that `repair` hasn't marked yet
+/// show up as `JobState::Unknown`
+pub fn list(queue_dir: &path::PathBuf) -> Result<Vec<Job>, OpsError> {
+    files::files(queue_dir)?
+        .into_iter()
+        .map(|entry| job(entry.filepath))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_id() {
+        let id = JobId::from("fnq1612345678901.4242");
+        assert_eq!(id.pid(), Some(4242));
+        assert_eq!(id.to_string(), "fnq1612345678901.4242");
+        assert_eq!(JobId::from("fnq1612345678901").pid(), None);
+    }
+}
@@ -0,0 +1,185 @@
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::prelude::*;
+use std::{env, ffi, fs, path, thread, time};
+
+use nix::{errno, fcntl, unistd};
+
+use crate::{IOResultExt, OpsError};
+
+// How often a task waiting for one of several slots checks whether one has freed up
+const SLOT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// `--lock <name>[:<slots>]`: a named lock that at most `slots` tasks hold at a time, whichever
+/// queue they are in
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedLock {
+    name: ffi::OsString,
+    slots: usize,
+}
+
+impl NamedLock {
+    /// Names become file names, so they can't be empty, contain `/` or start with `.`. `None` for
+    /// those and for 0 slots
+    pub fn new<S: AsRef<ffi::OsStr>>(name: S, slots: usize) -> Option<Self> {
+        let name = name.as_ref().as_bytes();
+        if name.is_empty() || name.starts_with(b".") || name.contains(&b'/') || slots == 0 {
+            return None;
+        }
+        Some(Self {
+            name: ffi::OsStr::from_bytes(name).into(),
+            slots,
+        })
+    }
+
+    /// Parses `name` or `name:slots`. Only the part after the last `:` is the number of slots
+    pub fn parse(arg: &ffi::OsStr) -> Option<Self> {
+        let bytes = arg.as_bytes();
+        match bytes.iter().rposition(|&byte| byte == b':') {
+            Some(split) => Self::new(
+                ffi::OsStr::from_bytes(&bytes[..split]),
+                std::str::from_utf8(&bytes[split + 1..])
+                    .ok()?
+                    .parse()
+                    .ok()?,
+            ),
+            None => Self::new(arg, 1),
+        }
+    }
+
+    /// The `--lock` value that would parse back into this lock
+    pub fn to_arg(&self) -> ffi::OsString {
+        match self.slots {
+            1 => self.name.clone(),
+            slots => concat_os_strings!(&self.name, ffi::OsString::from(format!(":{}", slots))),
+        }
+    }
+
+    fn slot_path(&self, locks_dir: &path::Path, slot: usize) -> path::PathBuf {
+        locks_dir.join(concat_os_strings!(
+            &self.name,
+            ffi::OsString::from(format!(".{}", slot))
+        ))
+    }
+}
+
+/// Where the lock files live: `$FNQ_LOCK_DIR`, else `$XDG_RUNTIME_DIR/fnq/locks`, else
+/// `fnq-<uid>/locks` in the temp dir. It isn't below any queue dir, so tasks of every queue share
+/// the same locks
+pub fn locks_dir() -> path::PathBuf {
+    match env::var_os("FNQ_LOCK_DIR") {
+        Some(dir) if !dir.is_empty() => path::PathBuf::from(dir),
+        _ => match env::var_os("XDG_RUNTIME_DIR") {
+            Some(runtime_dir) if !runtime_dir.is_empty() => {
+                path::PathBuf::from(runtime_dir).join("fnq").join("locks")
+            }
+            _ => env::temp_dir()
+                .join(format!("fnq-{}", unistd::getuid()))
+                .join("locks"),
+        },
+    }
+}
+
+fn open_slot(slot_path: &path::Path) -> Result<fs::File, OpsError> {
+    let slot_file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(slot_path)
+        .path_context("create", slot_path)?;
+    // The lock has to outlive exec, and is only released once the task and everything it spawned
+    // that inherited the descriptor have exited
+    fcntl::fcntl(
+        slot_file.as_raw_fd(),
+        fcntl::FcntlArg::F_SETFD(fcntl::FdFlag::empty()),
+    )?;
+    Ok(slot_file)
+}
+
+fn try_lock(slot_file: &fs::File, slot_path: &path::Path) -> Result<bool, OpsError> {
+    match fcntl::flock(
+        slot_file.as_raw_fd(),
+        fcntl::FlockArg::LockExclusiveNonblock,
+    ) {
+        Ok(_) => Ok(true),
+        Err(nix::Error::Sys(errno::EWOULDBLOCK)) => Ok(false),
+        Err(err) => Err(OpsError::Lock(slot_path.into(), err.to_string())),
+    }
+}
+
+fn acquire_one(locks_dir: &path::Path, lock: &NamedLock) -> Result<fs::File, OpsError> {
+    if lock.slots == 1 {
+        let slot_path = lock.slot_path(locks_dir, 0);
+        let slot_file = open_slot(&slot_path)?;
+        fcntl::flock(slot_file.as_raw_fd(), fcntl::FlockArg::LockExclusive)
+            .map_err(|err| OpsError::Lock(slot_path, err.to_string()))?;
+        return Ok(slot_file);
+    }
+
+    loop {
+        for slot in 0..lock.slots {
+            let slot_path = lock.slot_path(locks_dir, slot);
+            let slot_file = open_slot(&slot_path)?;
+            if try_lock(&slot_file, &slot_path)? {
+                return Ok(slot_file);
+            }
+        }
+        thread::sleep(SLOT_POLL_INTERVAL);
+    }
+}
+
+/// Blocks until a slot of every lock is held, and returns the locked files. Their descriptors are
+/// left open across exec, so the task holds the locks until it exits. Locks are taken in order of
+/// their names, so tasks that share several of them can't deadlock
+pub(crate) fn acquire(locks: &[NamedLock]) -> Result<Vec<fs::File>, OpsError> {
+    if locks.is_empty() {
+        return Ok(Vec::new());
+    }
+    let dir = locks_dir();
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .path_context("create", &dir)?;
+
+    let mut locks = locks.to_vec();
+    locks.sort_by(|a, b| a.name.cmp(&b.name));
+    locks.dedup_by(|a, b| a.name == b.name);
+    locks.iter().map(|lock| acquire_one(&dir, lock)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_named_lock() {
+        let parse = |arg: &str| NamedLock::parse(ffi::OsStr::new(arg));
+        assert_eq!(
+            parse("db"),
+            Some(NamedLock {
+                name: "db".into(),
+                slots: 1
+            })
+        );
+        assert_eq!(
+            parse("gpu:3"),
+            Some(NamedLock {
+                name: "gpu".into(),
+                slots: 3
+            })
+        );
+        assert_eq!(
+            parse("host:8080:2").map(|lock| lock.name),
+            Some("host:8080".into())
+        );
+        assert_eq!(parse(""), None);
+        assert_eq!(parse(":2"), None);
+        assert_eq!(parse("gpu:0"), None);
+        assert_eq!(parse("gpu:x"), None);
+        assert_eq!(parse("../db"), None);
+        assert_eq!(parse(".db"), None);
+        assert_eq!(parse("gpu:3").unwrap().to_arg(), "gpu:3");
+        assert_eq!(parse("db").unwrap().to_arg(), "db");
+    }
+}
@@ -0,0 +1,275 @@
+use std::io::{BufRead, Write};
+use std::os::unix::prelude::*;
+use std::{env, ffi, fs, io, path};
+
+use nix::fcntl;
+
+use crate::{IOResultExt, IoPriority, OpsError};
+
+/// Subdirectory of the queue dir that holds the metadata needed to queue a task again
+pub const META_DIR: &str = "meta";
+
+// Suffix of the marker that `--replace` leaves next to the metadata of a task it cancels
+const CANCELLED_SUFFIX: &str = ".cancelled";
+
+/// Everything needed to queue a task again, e.g. after a reboot dropped it
+#[derive(Debug, Default, PartialEq)]
+pub struct JobMeta {
+    pub argv: Vec<ffi::OsString>,
+    pub cwd: path::PathBuf,
+    pub env: Vec<(ffi::OsString, ffi::OsString)>,
+    /// fnq flags the task was queued with
+    pub options: Vec<ffi::OsString>,
+    /// From `--name`
+    pub name: Option<ffi::OsString>,
+    /// From `--label`
+    pub labels: Vec<(ffi::OsString, ffi::OsString)>,
+    /// From `--batch`
+    pub batch: Option<ffi::OsString>,
+    /// From `--nice`
+    pub nice: Option<i32>,
+    /// From `--ionice`
+    pub ionice: Option<IoPriority>,
+}
+
+impl JobMeta {
+    pub fn current(
+        argv: Vec<ffi::OsString>,
+        options: Vec<ffi::OsString>,
+    ) -> Result<Self, io::Error> {
+        Ok(Self {
+            argv,
+            cwd: env::current_dir()?,
+            env: env::vars_os().collect(),
+            options,
+            ..Self::default()
+        })
+    }
+}
+
+// Values are arbitrary bytes and fields are space separated, so anything that isn't printable
+// ascii (including spaces) is written as \xHH
+fn escape(value: &ffi::OsStr) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for &byte in value.as_bytes() {
+        if byte.is_ascii_graphic() && byte != b'\\' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &[u8]) -> Option<ffi::OsString> {
+    let mut unescaped = Vec::with_capacity(value.len());
+    let mut bytes = value.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\\' {
+            if bytes.next() != Some(&b'x') {
+                return None;
+            }
+            let hex = [*bytes.next()?, *bytes.next()?];
+            unescaped.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            unescaped.push(byte);
+        }
+    }
+    Some(ffi::OsString::from_vec(unescaped))
+}
+
+pub fn meta_path(queue_dir: &path::Path, queue_path: &path::Path) -> path::PathBuf {
+    let mut meta_path = queue_dir.join(META_DIR);
+    meta_path.push(queue_path.file_name().unwrap_or_default());
+    meta_path
+}
+
+fn create_meta_dir(queue_dir: &path::Path) -> Result<(), io::Error> {
+    match fs::create_dir(queue_dir.join(META_DIR)) {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Creates the metadata file of a queue file ahead of `write` and locks it. The supervisor holds
+/// the lock until it has written the status line, which tells a task whose supervisor died apart
+/// from one whose status line is about to be written
+pub fn lock(queue_dir: &path::Path, queue_path: &path::Path) -> Result<fs::File, io::Error> {
+    create_meta_dir(queue_dir)?;
+    let meta_file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(meta_path(queue_dir, queue_path))?;
+    if let Err(err) = fcntl::flock(meta_file.as_raw_fd(), fcntl::FlockArg::LockExclusive) {
+        let _ = fs::remove_file(meta_path(queue_dir, queue_path));
+        return Err(io::Error::other(err));
+    }
+    Ok(meta_file)
+}
+
+/// Whether the supervisor of a queue file is still around, see `lock`
+pub fn is_locked(queue_dir: &path::Path, queue_path: &path::Path) -> Result<bool, OpsError> {
+    let lock_path = meta_path(queue_dir, queue_path);
+    match fs::File::open(&lock_path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        meta_file => crate::is_locked(meta_file.path_context("open", &lock_path)?.as_raw_fd()),
+    }
+}
+
+pub fn write(
+    queue_dir: &path::Path,
+    queue_path: &path::Path,
+    meta: &JobMeta,
+) -> Result<(), io::Error> {
+    create_meta_dir(queue_dir)?;
+
+    // The environment may hold secrets, so keep this as private as the queue file itself
+    let mut meta_file = io::BufWriter::new(
+        fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o600)
+            .open(meta_path(queue_dir, queue_path))?,
+    );
+
+    writeln!(meta_file, "cwd {}", escape(meta.cwd.as_os_str()))?;
+    for arg in &meta.argv {
+        writeln!(meta_file, "argv {}", escape(arg))?;
+    }
+    for option in &meta.options {
+        writeln!(meta_file, "option {}", escape(option))?;
+    }
+    if let Some(name) = &meta.name {
+        writeln!(meta_file, "name {}", escape(name))?;
+    }
+    for (key, value) in &meta.labels {
+        writeln!(meta_file, "label {} {}", escape(key), escape(value))?;
+    }
+    if let Some(batch) = &meta.batch {
+        writeln!(meta_file, "batch {}", escape(batch))?;
+    }
+    if let Some(nice) = meta.nice {
+        writeln!(meta_file, "nice {}", nice)?;
+    }
+    if let Some(ionice) = meta.ionice {
+        writeln!(meta_file, "ionice {}", ionice)?;
+    }
+    for (key, value) in &meta.env {
+        writeln!(meta_file, "env {} {}", escape(key), escape(value))?;
+    }
+
+    meta_file.into_inner()?.sync_all()
+}
+
+/// Reads the metadata of a queue file. Returns `None` for queue files that have none, e.g. ones
+/// queued by older versions of fnq or whose task died before writing it
+pub fn read(queue_dir: &path::Path, queue_path: &path::Path) -> Result<Option<JobMeta>, io::Error> {
+    let meta_file = match fs::File::open(meta_path(queue_dir, queue_path)) {
+        Ok(meta_file) => meta_file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed queue file metadata");
+    let mut meta = JobMeta::default();
+
+    for line in io::BufReader::new(meta_file).split(b'\n') {
+        let line = line?;
+        let mut fields = line.splitn(3, |&byte| byte == b' ');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(b"cwd"), Some(cwd), None) => meta.cwd = unescape(cwd).ok_or_else(invalid)?.into(),
+            (Some(b"argv"), Some(arg), None) => meta.argv.push(unescape(arg).ok_or_else(invalid)?),
+            (Some(b"option"), Some(option), None) => {
+                meta.options.push(unescape(option).ok_or_else(invalid)?)
+            }
+            (Some(b"name"), Some(name), None) => {
+                meta.name = Some(unescape(name).ok_or_else(invalid)?)
+            }
+            (Some(b"batch"), Some(batch), None) => {
+                meta.batch = Some(unescape(batch).ok_or_else(invalid)?)
+            }
+            (Some(b"nice"), Some(nice), None) => {
+                meta.nice = Some(
+                    std::str::from_utf8(nice)
+                        .ok()
+                        .and_then(|nice| nice.parse().ok())
+                        .ok_or_else(invalid)?,
+                )
+            }
+            (Some(b"ionice"), Some(ionice), None) => {
+                meta.ionice = Some(
+                    std::str::from_utf8(ionice)
+                        .ok()
+                        .and_then(IoPriority::parse)
+                        .ok_or_else(invalid)?,
+                )
+            }
+            (Some(b"label"), Some(key), Some(value)) => meta.labels.push((
+                unescape(key).ok_or_else(invalid)?,
+                unescape(value).ok_or_else(invalid)?,
+            )),
+            (Some(b"env"), Some(key), Some(value)) => meta.env.push((
+                unescape(key).ok_or_else(invalid)?,
+                unescape(value).ok_or_else(invalid)?,
+            )),
+            _ => return Err(invalid()),
+        }
+    }
+
+    // Only locked by its supervisor, as the task never got to write it
+    if meta.argv.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(meta))
+}
+
+fn cancelled_path(queue_dir: &path::Path, queue_path: &path::Path) -> path::PathBuf {
+    let mut cancelled_path = meta_path(queue_dir, queue_path).into_os_string();
+    cancelled_path.push(CANCELLED_SUFFIX);
+    cancelled_path.into()
+}
+
+/// Marks a task as cancelled before it is killed, so that it doesn't start if it hasn't yet and
+/// its supervisor removes it instead of running hooks for it
+pub fn mark_cancelled(queue_dir: &path::Path, queue_path: &path::Path) -> Result<(), io::Error> {
+    create_meta_dir(queue_dir)?;
+    fs::write(cancelled_path(queue_dir, queue_path), "")
+}
+
+pub fn unmark_cancelled(queue_dir: &path::Path, queue_path: &path::Path) -> Result<(), io::Error> {
+    match fs::remove_file(cancelled_path(queue_dir, queue_path)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+pub fn is_cancelled(queue_dir: &path::Path, queue_path: &path::Path) -> bool {
+    cancelled_path(queue_dir, queue_path).exists()
+}
+
+/// Removes the metadata of a queue file, if it has any
+pub fn remove(queue_dir: &path::Path, queue_path: &path::Path) -> Result<(), io::Error> {
+    unmark_cancelled(queue_dir, queue_path)?;
+    match fs::remove_file(meta_path(queue_dir, queue_path)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        let raw = ffi::OsString::from_vec(b"a b\\c\n\xff=".to_vec());
+        let escaped = escape(&raw);
+        assert_eq!(escaped, "a\\x20b\\x5cc\\x0a\\xff=");
+        assert_eq!(unescape(escaped.as_bytes()), Some(raw));
+        assert_eq!(unescape(b"\\n"), None);
+        assert_eq!(unescape(b"\\x4"), None);
+    }
+}
@@ -0,0 +1,133 @@
+use std::os::unix::prelude::*;
+use std::sync::mpsc;
+use std::{ffi, path, time};
+
+use notify::{raw_watcher, RawEvent, RecursiveMode, Watcher};
+
+use crate::clean::ARCHIVE_DIR;
+use crate::meta::META_DIR;
+use crate::queue::find_executable;
+use crate::schedule::SCHEDULES_DIR;
+use crate::{job, queue, IOResultExt, JobState, OpsError, QueueOptions, QUEUE_FILE_PREFIX};
+
+/// How long the watched paths have to stay unchanged before a burst of changes counts as one
+pub const DEBOUNCE: time::Duration = time::Duration::from_millis(500);
+
+/// Whether `changed` is something fnq itself writes to in the queue dir, which may well be inside
+/// the watched paths
+fn is_queue_dir_entry(changed: &path::Path, queue_dir: &path::Path) -> bool {
+    let mut components = match changed.strip_prefix(queue_dir) {
+        Ok(relative) => relative.components(),
+        Err(_) => return false,
+    };
+    let name = match components.next() {
+        Some(path::Component::Normal(name)) => name.as_bytes(),
+        _ => return false,
+    };
+    let is_sidecar_dir = [META_DIR, ARCHIVE_DIR, SCHEDULES_DIR]
+        .iter()
+        .any(|dir| name == dir.as_bytes());
+    let is_queue_file = name.starts_with(QUEUE_FILE_PREFIX.as_bytes())
+        // Temporary files of --compress
+        || (name.starts_with(b".") && name.ends_with(b".gz.tmp"));
+    is_sidecar_dir || (is_queue_file && components.next().is_none())
+}
+
+fn is_change(event: RawEvent, queue_dir: &path::Path) -> Result<bool, OpsError> {
+    match event.op {
+        Err(err) => Err(OpsError::Watcher(err)),
+        Ok(_) => Ok(event
+            .path
+            .is_none_or(|changed| !is_queue_dir_entry(&changed, queue_dir))),
+    }
+}
+
+fn next_event(rx: &mpsc::Receiver<RawEvent>) -> Result<RawEvent, OpsError> {
+    rx.recv()
+        .map_err(|_| OpsError::WatcherUnknown("Watcher stopped".into()))
+}
+
+/// Watches `paths` recursively and queues the task whenever they change, until interrupted.
+/// Prints the queue file name of every task it queues unless `options.quiet` is set. A change
+/// doesn't queue the task again while the one queued before is still waiting, since that one
+/// will see the change once it runs
+pub fn on_change(
+    fnd_cmd: ffi::OsString,
+    task_cmd: ffi::OsString,
+    task_args: Vec<ffi::OsString>,
+    queue_dir: path::PathBuf,
+    options: QueueOptions,
+    paths: &[path::PathBuf],
+) -> Result<(), OpsError> {
+    find_executable(&task_cmd)?;
+    // Events carry the path that was watched joined with the changed name
+    let queue_dir_path = queue_dir.canonicalize().path_context("open", &queue_dir)?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = raw_watcher(tx)?;
+    for watched in paths {
+        let watched = watched.canonicalize().path_context("watch", watched)?;
+        watcher.watch(&watched, RecursiveMode::Recursive)?;
+    }
+
+    let mut last_queued: Option<path::PathBuf> = None;
+    loop {
+        while !is_change(next_event(&rx)?, &queue_dir_path)? {}
+        let mut quiet_until = time::Instant::now() + DEBOUNCE;
+        loop {
+            match rx.recv_timeout(quiet_until.saturating_duration_since(time::Instant::now())) {
+                Ok(event) => {
+                    if is_change(event, &queue_dir_path)? {
+                        quiet_until = time::Instant::now() + DEBOUNCE;
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(OpsError::WatcherUnknown("Watcher stopped".into()))
+                }
+            }
+        }
+
+        let waiting = last_queued.as_ref().is_some_and(|queue_path| {
+            matches!(
+                job(queue_path.clone()).map(|job| job.state),
+                Ok(JobState::Waiting)
+            )
+        });
+        if waiting {
+            continue;
+        }
+
+        let queue_file = queue(
+            fnd_cmd.clone(),
+            task_cmd.clone(),
+            task_args.clone(),
+            queue_dir.clone(),
+            options.clone(),
+        )?;
+        if !options.quiet {
+            println!("{}", queue_file.to_string_lossy());
+        }
+        last_queued = Some(queue_dir.join(queue_file));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_queue_dir_entry() {
+        let is_entry =
+            |changed: &str| is_queue_dir_entry(path::Path::new(changed), path::Path::new("/work"));
+        assert!(is_entry("/work/fnq1.2"));
+        assert!(is_entry("/work/.fnq1.2.gz.tmp"));
+        assert!(is_entry("/work/meta"));
+        assert!(is_entry("/work/meta/fnq1.2"));
+        assert!(is_entry("/work/schedules/schedule1.2"));
+        assert!(!is_entry("/work/src/main.rs"));
+        assert!(!is_entry("/work/src/fnq1.2"));
+        assert!(!is_entry("/work/fnq1.2/notes"));
+        assert!(!is_entry("/elsewhere/fnq1.2"));
+    }
+}
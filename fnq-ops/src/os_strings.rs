@@ -0,0 +1,223 @@
+use std::ffi;
+use std::os::unix::prelude::*;
+
+// TODO: should count amount of $elem and use with capacity
+#[macro_export]
+macro_rules! concat_os_strings {
+    ($($elem: expr),*) => {{
+        let mut os_string = ffi::OsString::new();
+        $(os_string.push($elem);)*
+        os_string
+    }};
+}
+
+pub trait OsStringStartsWithExt {
+    fn starts_with(&self, rhs: &ffi::OsStr) -> bool;
+}
+
+impl OsStringStartsWithExt for &ffi::OsStr {
+    fn starts_with(&self, rhs: &ffi::OsStr) -> bool {
+        let bytes_a = self.as_bytes();
+        let bytes_b = rhs.as_bytes();
+        if bytes_b.len() > bytes_a.len() {
+            return false;
+        }
+        for (i, b) in bytes_a[..bytes_b.len()].iter().enumerate() {
+            if !bytes_b[i].eq(b) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn is_shell_safe(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(&byte)
+}
+
+/// Quotes an argument so a shell reads it back as the exact same bytes. Control characters are
+/// written as `$'...'` escapes, which keeps the quoted argument on a single line
+pub fn shell_quote(arg: &ffi::OsStr) -> ffi::OsString {
+    let bytes = arg.as_bytes();
+    if !bytes.is_empty() && bytes.iter().all(|&byte| is_shell_safe(byte)) {
+        return arg.to_os_string();
+    }
+
+    let mut quoted = Vec::with_capacity(bytes.len() + 2);
+    quoted.push(b'\'');
+    for &byte in bytes {
+        match byte {
+            b'\'' => quoted.extend_from_slice(b"'\\''"),
+            b'\n' => quoted.extend_from_slice(b"'$'\\n''"),
+            b'\t' => quoted.extend_from_slice(b"'$'\\t''"),
+            b'\r' => quoted.extend_from_slice(b"'$'\\r''"),
+            byte if byte.is_ascii_control() => {
+                quoted.extend_from_slice(format!("'$'\\x{:02x}''", byte).as_bytes())
+            }
+            byte => quoted.push(byte),
+        }
+    }
+    quoted.push(b'\'');
+    ffi::OsString::from_vec(quoted)
+}
+
+/// Quotes and joins arguments into a line that can be pasted into a shell
+pub fn shell_join<'a, I>(args: I) -> ffi::OsString
+where
+    I: IntoIterator<Item = &'a ffi::OsString>,
+{
+    let mut line = Vec::new();
+    for (i, arg) in args.into_iter().enumerate() {
+        if i > 0 {
+            line.push(b' ');
+        }
+        line.extend_from_slice(shell_quote(arg).as_bytes());
+    }
+    ffi::OsString::from_vec(line)
+}
+
+// Byte that a backslash escape inside `$'...'` stands for, after the backslash
+fn unescape_ansi_c(bytes: &mut std::slice::Iter<u8>) -> Option<u8> {
+    match *bytes.next()? {
+        b'n' => Some(b'\n'),
+        b't' => Some(b'\t'),
+        b'r' => Some(b'\r'),
+        b'x' => {
+            let mut value = 0;
+            for _ in 0..2 {
+                match bytes
+                    .as_slice()
+                    .first()
+                    .and_then(|&byte| (byte as char).to_digit(16))
+                {
+                    Some(digit) => {
+                        bytes.next();
+                        value = value * 16 + digit as u8;
+                    }
+                    None => break,
+                }
+            }
+            Some(value)
+        }
+        byte => Some(byte),
+    }
+}
+
+/// Splits a line written by `shell_join` back into arguments. Also understands double quotes and
+/// backslash escapes. Returns `None` for unterminated quotes
+pub fn shell_split(line: &ffi::OsStr) -> Option<Vec<ffi::OsString>> {
+    let mut args = Vec::new();
+    let mut arg: Option<Vec<u8>> = None;
+    let mut bytes = line.as_bytes().iter();
+
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b' ' | b'\t' | b'\n' => {
+                if let Some(arg) = arg.take() {
+                    args.push(ffi::OsString::from_vec(arg));
+                }
+            }
+            b'\'' => {
+                let arg = arg.get_or_insert_with(Vec::new);
+                loop {
+                    match *bytes.next()? {
+                        b'\'' => break,
+                        byte => arg.push(byte),
+                    }
+                }
+            }
+            b'$' if bytes.as_slice().first() == Some(&b'\'') => {
+                bytes.next();
+                let arg = arg.get_or_insert_with(Vec::new);
+                loop {
+                    match *bytes.next()? {
+                        b'\'' => break,
+                        b'\\' => arg.push(unescape_ansi_c(&mut bytes)?),
+                        byte => arg.push(byte),
+                    }
+                }
+            }
+            b'"' => {
+                let arg = arg.get_or_insert_with(Vec::new);
+                loop {
+                    match *bytes.next()? {
+                        b'"' => break,
+                        b'\\' => arg.push(*bytes.next()?),
+                        byte => arg.push(byte),
+                    }
+                }
+            }
+            b'\\' => arg.get_or_insert_with(Vec::new).push(*bytes.next()?),
+            byte => arg.get_or_insert_with(Vec::new).push(byte),
+        }
+    }
+    if let Some(arg) = arg {
+        args.push(ffi::OsString::from_vec(arg));
+    }
+
+    Some(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concat_macro() {
+        let hello: ffi::OsString = "hello".into();
+        let world: ffi::OsString = "world".into();
+
+        assert_eq!(
+            concat_os_strings!(hello, world),
+            ffi::OsString::from("helloworld")
+        )
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        let quote = |arg: &str| shell_quote(ffi::OsStr::new(arg));
+        assert_eq!(quote("sleep"), "sleep");
+        assert_eq!(quote("./task-1.sh"), "./task-1.sh");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("a\nb"), "'a'$'\\n''b'");
+        assert_eq!(quote("\x1b[0m"), "''$'\\x1b''[0m'");
+
+        let args: Vec<ffi::OsString> = vec![
+            "sh".into(),
+            "-c".into(),
+            "echo \"$1\" it's".into(),
+            "".into(),
+            ffi::OsString::from_vec(b"\xff\xfe name".to_vec()),
+            "line one\nline two\r\t\x07".into(),
+        ];
+        let line = shell_join(&args);
+        assert!(!line.as_bytes().contains(&b'\n'));
+        assert_eq!(shell_split(&line), Some(args));
+    }
+
+    #[test]
+    fn test_shell_split() {
+        let split = |line: &str| shell_split(ffi::OsStr::new(line));
+        let os_strings = |args: &[&str]| {
+            Some(args.iter().map(ffi::OsString::from).collect::<Vec<_>>())
+        };
+        assert_eq!(split("  sleep   2 "), os_strings(&["sleep", "2"]));
+        assert_eq!(split("echo \"a \\\"b\\\"\" c\\ d"), os_strings(&["echo", "a \"b\"", "c d"]));
+        assert_eq!(split("'a'b''"), os_strings(&["ab"]));
+        assert_eq!(split("$'a\\'b\\x41\\x4' $c"), os_strings(&["a'bA\x04", "$c"]));
+        assert_eq!(split("echo 'a"), None);
+        assert_eq!(split("echo \"a"), None);
+    }
+
+    #[test]
+    fn test_starts_with() {
+        let foobar: ffi::OsString = "foobar".into();
+        assert!(foobar
+            .as_os_str()
+            .starts_with(ffi::OsString::from("foo").as_os_str()));
+    }
+}
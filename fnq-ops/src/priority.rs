@@ -0,0 +1,182 @@
+use std::{fmt, fs, mem};
+
+use nix::libc;
+
+use crate::OpsError;
+
+// ioprio_set(2) takes the class in the bits above the level
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+// Bits of the capabilities in the CapEff line of /proc/self/status, from linux/capability.h
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_SYS_NICE: u32 = 23;
+
+/// Lowest and highest `--nice` values, as with nice(1)
+pub const NICE_RANGE: std::ops::RangeInclusive<i32> = -20..=19;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IoClass {
+    /// Gets disk time before anything else. Needs root
+    Realtime,
+    /// The default class, where the level decides
+    BestEffort,
+    /// Only gets disk time when nothing else wants it
+    Idle,
+}
+
+/// `--ionice <class>[:<level>]`: IO scheduling class of the task, and for the realtime and
+/// best-effort classes a level from 0 (highest) to 7 (lowest)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoPriority {
+    pub class: IoClass,
+    pub level: Option<u8>,
+}
+
+impl fmt::Display for IoPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class = match self.class {
+            IoClass::Realtime => "realtime",
+            IoClass::BestEffort => "best-effort",
+            IoClass::Idle => "idle",
+        };
+        match self.level {
+            Some(level) => write!(f, "{}:{}", class, level),
+            None => write!(f, "{}", class),
+        }
+    }
+}
+
+impl IoPriority {
+    /// Parses `idle`, `best-effort`, `realtime`, either of the latter two followed by `:<level>`,
+    /// or the same with the class numbers of ionice(1): 1, 2 and 3
+    pub fn parse(arg: &str) -> Option<Self> {
+        let (class, level) = match arg.split_once(':') {
+            Some((class, level)) => (class, Some(level.parse().ok().filter(|&level| level <= 7)?)),
+            None => (arg, None),
+        };
+        let class = match class {
+            "realtime" | "1" => IoClass::Realtime,
+            "best-effort" | "2" => IoClass::BestEffort,
+            "idle" | "3" if level.is_none() => IoClass::Idle,
+            _ => return None,
+        };
+        Some(Self { class, level })
+    }
+
+    fn ioprio(&self) -> libc::c_int {
+        let class = match self.class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        let level = match self.class {
+            IoClass::Idle => 0,
+            // What the kernel uses when no level is given
+            _ => self.level.unwrap_or(4),
+        };
+        class << IOPRIO_CLASS_SHIFT | libc::c_int::from(level)
+    }
+}
+
+/// Whether the calling process has `capability` in its effective set
+fn has_capability(capability: u32) -> bool {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let caps = status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))?;
+            u64::from_str_radix(caps.trim(), 16).ok()
+        })
+        .is_some_and(|caps| caps & (1 << capability) != 0)
+}
+
+/// Whether the calling process may lower its nice value to `nice`, the way the kernel decides it:
+/// `RLIMIT_NICE` allows going down to 20 minus the limit, and `CAP_SYS_NICE` to anything
+fn can_nice(nice: i32) -> bool {
+    let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+    if nice >= current || has_capability(CAP_SYS_NICE) {
+        return true;
+    }
+    let mut rlimit: libc::rlimit = unsafe { mem::zeroed() };
+    unsafe { libc::getrlimit(libc::RLIMIT_NICE, &mut rlimit) };
+    (20 - nice) as libc::rlim_t <= rlimit.rlim_cur
+}
+
+/// Fails when `apply` would for lack of privilege, so that is found out when the task is queued
+/// rather than once its turn has come
+pub(crate) fn check(nice: Option<i32>, ionice: Option<IoPriority>) -> Result<(), OpsError> {
+    if let Some(nice) = nice.filter(|&nice| !can_nice(nice)) {
+        return Err(OpsError::Unknown(format!(
+            "--nice {} is below the current nice value and needs CAP_SYS_NICE",
+            nice
+        )));
+    }
+    let realtime = ionice.is_some_and(|ionice| ionice.class == IoClass::Realtime);
+    if realtime && !has_capability(CAP_SYS_NICE) && !has_capability(CAP_SYS_ADMIN) {
+        return Err(OpsError::Unknown(
+            "--ionice realtime needs CAP_SYS_NICE or CAP_SYS_ADMIN".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Sets the CPU and IO priority of the calling process. Nice values below the current one need
+/// CAP_SYS_NICE, and the realtime IO class needs root
+pub(crate) fn apply(nice: Option<i32>, ionice: Option<IoPriority>) -> Result<(), OpsError> {
+    if let Some(nice) = nice {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+            return Err(OpsError::Unix(format!(
+                "Could not set nice value {}: {}",
+                nice,
+                nix::Error::last()
+            )));
+        }
+    }
+    if let Some(ionice) = ionice {
+        let ioprio = ionice.ioprio();
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+            return Err(OpsError::Unix(format!(
+                "Could not set IO priority {}: {}",
+                ionice,
+                nix::Error::last()
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_io_priority() {
+        let idle = IoPriority {
+            class: IoClass::Idle,
+            level: None,
+        };
+        assert_eq!(IoPriority::parse("idle"), Some(idle));
+        assert_eq!(IoPriority::parse("3"), Some(idle));
+        assert_eq!(
+            IoPriority::parse("best-effort:7"),
+            Some(IoPriority {
+                class: IoClass::BestEffort,
+                level: Some(7)
+            })
+        );
+        assert_eq!(IoPriority::parse("idle:1"), None);
+        assert_eq!(IoPriority::parse("best-effort:8"), None);
+        assert_eq!(IoPriority::parse("low"), None);
+        assert_eq!(
+            IoPriority::parse("2:0").unwrap().to_string(),
+            "best-effort:0"
+        );
+        assert_eq!(IoPriority::parse("idle").unwrap().ioprio(), 3 << 13);
+        assert_eq!(
+            IoPriority::parse("best-effort").unwrap().ioprio(),
+            2 << 13 | 4
+        );
+    }
+}
@@ -0,0 +1,916 @@
+use std::io::{Read, Write};
+use std::os::unix::prelude::*;
+use std::sync::{self, mpsc, PoisonError};
+use std::{env, ffi, fs, io, iter, mem, path, process, thread, time};
+
+use nix::sys::signal::{self, Signal};
+use nix::{errno, fcntl, libc, sys, unistd};
+
+use crate::admission;
+use crate::barrier::{self, BARRIER_ARG};
+use crate::cgroup::{self, CgroupLimits, JobCgroup};
+use crate::hooks::{self, Hook};
+use crate::limits::{self, Limits};
+use crate::locks::{self, NamedLock};
+use crate::priority::{self, IoPriority};
+use crate::{
+    block_on_locked_file, clean, compress, files, gc, job, kill, meta, os_strings::shell_join,
+    status, wait_for_status, CleanPolicy, GcPolicy, IOResultExt, Job, JobState, OpsError,
+    QUEUE_FILE_PREFIX,
+};
+
+/// What `queue` does when an identical task, i.e. the same argv queued from the same cwd, is
+/// already waiting for its turn
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+    #[default]
+    Allow,
+    /// Returns the queue file of the waiting task instead of queueing another one
+    Unique,
+    /// Kills and removes the waiting task, then queues this one
+    Replace,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct QueueOptions {
+    /// Skips printing the queue file name to stdout
+    pub quiet: bool,
+    /// Which exit outcomes remove the queue file once the task completes
+    pub clean: CleanPolicy,
+    /// Once the task completes, keeps only this many finished queue files in the queue dir
+    pub keep_last: Option<usize>,
+    /// Moves cleaned queue files gzipped into the archive dir instead of deleting them
+    pub archive: bool,
+    /// Gzips the queue file once the task completes
+    pub compress: bool,
+    /// Shell command to run once the task exits with status 0
+    pub on_success: Option<ffi::OsString>,
+    /// Shell command to run once the task did not succeed
+    pub on_failure: Option<ffi::OsString>,
+    /// Kills the task once it has run for this long, with SIGTERM and then SIGKILL. Rounded up to
+    /// whole seconds
+    pub timeout: Option<time::Duration>,
+    /// Name to refer to the job by instead of its queue file name
+    pub name: Option<ffi::OsString>,
+    /// Key and value pairs that listings can be filtered by
+    pub labels: Vec<(ffi::OsString, ffi::OsString)>,
+    /// Group of jobs that can be waited on together
+    pub batch: Option<ffi::OsString>,
+    /// From `--unique` or `--replace`
+    pub duplicates: DuplicatePolicy,
+    /// Named locks the task holds while it runs, on top of its turn in the queue
+    pub locks: Vec<NamedLock>,
+    /// Once its turn has come, holds the task back until this many bytes of memory are available
+    pub min_memory: Option<u64>,
+    /// Once its turn has come, holds the task back until the 1 minute load average is below this
+    pub max_load: Option<f64>,
+    /// Applied to the task with `setrlimit` right before it execs
+    pub limits: Limits,
+    /// Enforced by the cgroup the task runs in
+    pub cgroup: CgroupLimits,
+    /// Nice value of the task
+    pub nice: Option<i32>,
+    /// IO scheduling class and level of the task
+    pub ionice: Option<IoPriority>,
+}
+
+impl QueueOptions {
+    /// The fnq flags that would parse back into these options. `quiet` and `duplicates` only
+    /// concern the queueing process rather than the task, so they are left out
+    pub fn to_args(&self) -> Vec<ffi::OsString> {
+        let mut args: Vec<ffi::OsString> = Vec::new();
+        match self.clean {
+            CleanPolicy::Never => {}
+            CleanPolicy::Success => args.push("--clean=success".into()),
+            CleanPolicy::Failure => args.push("--clean=failure".into()),
+            CleanPolicy::Always => args.push("--clean=always".into()),
+        }
+        if let Some(keep_last) = self.keep_last {
+            args.push("--keep-last".into());
+            args.push(keep_last.to_string().into());
+        }
+        if self.archive {
+            args.push("--archive".into());
+        }
+        if self.compress {
+            args.push("--compress".into());
+        }
+        if let Some(on_success) = &self.on_success {
+            args.push("--on-success".into());
+            args.push(on_success.clone());
+        }
+        if let Some(on_failure) = &self.on_failure {
+            args.push("--on-failure".into());
+            args.push(on_failure.clone());
+        }
+        if let Some(timeout) = self.timeout {
+            args.push("--timeout".into());
+            args.push(format!("{}s", timeout_secs(timeout)).into());
+        }
+        if let Some(name) = &self.name {
+            args.push("--name".into());
+            args.push(name.clone());
+        }
+        for (key, value) in &self.labels {
+            args.push("--label".into());
+            args.push(concat_os_strings!(key, ffi::OsStr::new("="), value));
+        }
+        if let Some(batch) = &self.batch {
+            args.push("--batch".into());
+            args.push(batch.clone());
+        }
+        for lock in &self.locks {
+            args.push("--lock".into());
+            args.push(lock.to_arg());
+        }
+        if let Some(min_memory) = self.min_memory {
+            args.push("--min-memory".into());
+            args.push(min_memory.to_string().into());
+        }
+        if let Some(max_load) = self.max_load {
+            args.push("--max-load".into());
+            args.push(max_load.to_string().into());
+        }
+        args.extend(self.limits.to_args());
+        args.extend(self.cgroup.to_args());
+        if let Some(nice) = self.nice {
+            args.push("--nice".into());
+            args.push(nice.to_string().into());
+        }
+        if let Some(ionice) = self.ionice {
+            args.push("--ionice".into());
+            args.push(ionice.to_string().into());
+        }
+        args
+    }
+
+    /// Same as `to_args`, but also records the options the config file can give a default for
+    /// when they are unset, as `--clean=never` or e.g. `--timeout none`. Replaying these queues
+    /// the task with the same options whatever the config file says by then
+    pub fn to_replay_args(&self) -> Vec<ffi::OsString> {
+        let mut args: Vec<ffi::OsString> = Vec::new();
+        if self.clean == CleanPolicy::Never {
+            args.push("--clean=never".into());
+        }
+        for (flag, unset) in [
+            ("--timeout", self.timeout.is_none()),
+            ("--min-memory", self.min_memory.is_none()),
+            ("--max-load", self.max_load.is_none()),
+            ("--nice", self.nice.is_none()),
+            ("--ionice", self.ionice.is_none()),
+        ] {
+            if unset {
+                args.push(flag.into());
+                args.push("none".into());
+            }
+        }
+        args.extend(self.to_args());
+        args
+    }
+}
+
+pub(crate) fn timeout_secs(timeout: time::Duration) -> u64 {
+    let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+    // A timeout of 0 seconds would kill the task before it got to do anything
+    secs.max(1)
+}
+
+/// How long a task that timed out gets to exit after SIGTERM before it is sent SIGKILL
+const TIMEOUT_KILL_AFTER: time::Duration = time::Duration::from_secs(10);
+
+/// Exit code hooks get for a task that timed out, the same one `timeout(1)` uses
+const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+struct TaskFileHandler {
+    pub queue_dir: path::PathBuf,
+    cmd: ffi::OsString,
+    args: Vec<ffi::OsString>,
+    time_id: String,
+    pid: Option<u32>,
+}
+
+impl TaskFileHandler {
+    fn set_pid(&mut self, pid: u32) {
+        self.pid = Some(pid);
+    }
+
+    fn new(
+        queue_dir: path::PathBuf,
+        cmd: ffi::OsString,
+        args: Vec<ffi::OsString>,
+    ) -> Result<Self, time::SystemTimeError> {
+        let time_id = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)?
+            .as_millis()
+            .to_string();
+        Ok(Self {
+            queue_dir,
+            cmd,
+            args,
+            time_id,
+            pid: None,
+        })
+    }
+
+    fn filename(&self) -> ffi::OsString {
+        match self.pid {
+            None => panic!("Has not set pid on task file handler yet"),
+            Some(pid) => concat_os_strings!(
+                ffi::OsString::from(QUEUE_FILE_PREFIX),
+                ffi::OsString::from(&self.time_id),
+                ffi::OsString::from("."),
+                ffi::OsString::from(pid.to_string())
+            ),
+        }
+    }
+
+    fn path(&self) -> path::PathBuf {
+        let mut file_path = self.queue_dir.clone();
+        file_path.push(self.filename());
+        file_path
+    }
+}
+
+/// Ends fnq's own options in front of a task command
+pub(crate) const END_OF_OPTIONS: &str = "--";
+
+/// Arguments that queue a task again when passed to fnq after its flags. The `--` keeps a task
+/// named like a subcommand, e.g. `ls`, or starting with `-` from being taken for one. A barrier is
+/// queued by its flag and so has none
+pub(crate) fn requeue_args(argv: &[ffi::OsString]) -> Vec<ffi::OsString> {
+    if argv == [ffi::OsString::from(BARRIER_ARG)] {
+        return argv.to_vec();
+    }
+    iter::once(END_OF_OPTIONS.into())
+        .chain(argv.iter().cloned())
+        .collect()
+}
+
+/// Resolves `cmd` the same way `execvp` will, so a missing or non-executable command fails before
+/// anything is queued
+pub(crate) fn find_executable(cmd: &ffi::OsStr) -> Result<path::PathBuf, OpsError> {
+    let is_executable = |path: &path::Path| {
+        path.is_file() && unistd::access(path, unistd::AccessFlags::X_OK).is_ok()
+    };
+
+    if cmd.as_bytes().contains(&b'/') {
+        let path = path::PathBuf::from(cmd);
+        if !path.exists() {
+            return Err(OpsError::CommandNotFound(cmd.into()));
+        } else if !is_executable(&path) {
+            return Err(OpsError::NotExecutable(cmd.into()));
+        }
+        return Ok(path);
+    }
+
+    let search_path = env::var_os("PATH").unwrap_or_else(|| "/bin:/usr/bin".into());
+    let mut found = None;
+    for dir in env::split_paths(&search_path) {
+        let path = dir.join(cmd);
+        if is_executable(&path) {
+            return Ok(path);
+        } else if path.exists() {
+            found = Some(path);
+        }
+    }
+
+    match found {
+        Some(_) => Err(OpsError::NotExecutable(cmd.into())),
+        None => Err(OpsError::CommandNotFound(cmd.into())),
+    }
+}
+
+/// Closes every file descriptor except stdio and `keep`, so the supervisor and task don't hold on
+/// to anything the queueing process had open (e.g. the ready pipe of another task being queued)
+pub(crate) fn close_inherited_fds(keep: RawFd) -> Result<(), OpsError> {
+    let fds: Vec<RawFd> = fs::read_dir("/dev/fd")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds {
+        if fd > 2 && fd != keep {
+            // One of them was the directory listing itself, which is closed by now
+            let _ = unistd::close(fd);
+        }
+    }
+    Ok(())
+}
+
+/// Forked processes must never return into the code that called `queue`
+pub(crate) fn exit_forked(result: Result<(), OpsError>) -> ! {
+    match result {
+        Ok(_) => process::exit(0),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Queues the task and returns the name of its queue file once the task has taken its place in
+/// the queue. The task is run by a detached supervising process, which waits for its turn and
+/// appends the status line once it exits
+pub fn queue(
+    fnd_cmd: ffi::OsString,
+    task_cmd: ffi::OsString,
+    task_args: Vec<ffi::OsString>,
+    queue_dir: path::PathBuf,
+    options: QueueOptions,
+) -> Result<ffi::OsString, OpsError> {
+    find_executable(&task_cmd)?;
+    // Fails right away rather than once its turn comes where /proc can't be read
+    admission::admits(options.min_memory, options.max_load)?;
+    options.limits.check()?;
+    options.cgroup.check()?;
+    priority::check(options.nice, options.ionice)?;
+
+    if options.duplicates != DuplicatePolicy::Allow {
+        let mut argv = task_args.clone();
+        argv.insert(0, task_cmd.clone());
+        let duplicates = waiting_duplicates(&queue_dir, &argv)?;
+        match (options.duplicates, duplicates.last()) {
+            (DuplicatePolicy::Unique, Some(duplicate)) => {
+                return Ok(duplicate.file_name().unwrap_or_default().into());
+            }
+            (DuplicatePolicy::Replace, _) => {
+                for duplicate in &duplicates {
+                    cancel(&queue_dir, duplicate)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    spawn(fnd_cmd, task_cmd, task_args, queue_dir, options)
+}
+
+/// Queue files of the tasks waiting for their turn that have the same argv and cwd as the task
+/// about to be queued
+fn waiting_duplicates(
+    queue_dir: &path::PathBuf,
+    argv: &[ffi::OsString],
+) -> Result<Vec<path::PathBuf>, OpsError> {
+    let cwd = env::current_dir()?;
+    let mut duplicates = Vec::new();
+    for entry in files::files(queue_dir)? {
+        match job(entry.filepath.clone()) {
+            Ok(Job {
+                state: JobState::Waiting,
+                ..
+            }) => {}
+            // Started, finished or removed since it was listed
+            _ => continue,
+        }
+        if let Ok(Some(job_meta)) = meta::read(queue_dir, &entry.filepath) {
+            if job_meta.argv == argv && job_meta.cwd == cwd {
+                duplicates.push(entry.filepath);
+            }
+        }
+    }
+    Ok(duplicates)
+}
+
+/// Kills a waiting task and removes its queue file. Its supervisor removes it already, unless it
+/// finished before it could be killed
+fn cancel(queue_dir: &path::Path, queue_path: &path::PathBuf) -> Result<(), OpsError> {
+    meta::mark_cancelled(queue_dir, queue_path).path_context("mark as cancelled", queue_path)?;
+    if let Err(err) = kill(std::slice::from_ref(queue_path), Signal::SIGTERM) {
+        meta::unmark_cancelled(queue_dir, queue_path)
+            .path_context("unmark as cancelled", queue_path)?;
+        return match err {
+            // Finished in the meantime
+            OpsError::JobNotRunning(_) => Ok(()),
+            err => Err(err),
+        };
+    }
+    wait_for_status(queue_path)?;
+    match clean::remove(queue_dir, queue_path, false) {
+        // Removed by its supervisor or its own --clean
+        Err(OpsError::PathIO(_, _, err)) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Queues a barrier: a marker without a command that waits for every job queued before it, the
+/// same way a task would, and then exits with status 0 if all of those since the previous barrier
+/// succeeded, or 1 after listing the ones that didn't. Jobs queued after it thereby wait for all
+/// of them as well
+pub fn queue_barrier(
+    fnd_cmd: ffi::OsString,
+    queue_dir: path::PathBuf,
+    options: QueueOptions,
+) -> Result<ffi::OsString, OpsError> {
+    spawn(fnd_cmd, BARRIER_ARG.into(), Vec::new(), queue_dir, options)
+}
+
+fn spawn(
+    fnd_cmd: ffi::OsString,
+    task_cmd: ffi::OsString,
+    task_args: Vec<ffi::OsString>,
+    queue_dir: path::PathBuf,
+    options: QueueOptions,
+) -> Result<ffi::OsString, OpsError> {
+    let task_handler = TaskFileHandler::new(queue_dir, task_cmd, task_args)?;
+    // The task sends its queue file name through this once it has locked it and written its exec
+    // line. It reaches EOF without one if queueing failed
+    let pipe = unistd::pipe()?;
+    let child_fork = unsafe { unistd::fork()? };
+    match child_fork {
+        unistd::ForkResult::Parent { child } => {
+            unistd::close(pipe.1)?;
+            let mut ready = unsafe { fs::File::from_raw_fd(pipe.0) };
+            // Only forks the supervisor, so this doesn't leave a zombie behind
+            sys::wait::waitpid(child, None)?;
+
+            // Will wait until grandchild process is ready
+            let mut task_filename = Vec::new();
+            ready.read_to_end(&mut task_filename)?;
+            if task_filename.is_empty() {
+                return Err(OpsError::Unknown("Task could not be queued".into()));
+            }
+            Ok(ffi::OsString::from_vec(task_filename))
+        }
+        unistd::ForkResult::Child => exit_forked(detach(fnd_cmd, task_handler, options, pipe)),
+    }
+}
+
+fn detach(
+    fnd_cmd: ffi::OsString,
+    task_handler: TaskFileHandler,
+    options: QueueOptions,
+    pipe: (RawFd, RawFd),
+) -> Result<(), OpsError> {
+    unistd::close(pipe.0)?;
+    close_inherited_fds(pipe.1)?;
+    if let unistd::ForkResult::Child = unsafe { unistd::fork()? } {
+        exit_forked(supervise(fnd_cmd, task_handler, options, pipe.1));
+    }
+    Ok(())
+}
+
+/// Whether the task got as far as writing the exec line into its queue file, after which queueing
+/// it succeeded
+fn is_set_up(queue_path: &path::Path) -> bool {
+    fs::File::open(queue_path)
+        .and_then(status::read_header)
+        .is_ok_and(|header| header.exec_args().is_some())
+}
+
+/// How far the grandchild got, as reported on the exec pipe
+enum Exec {
+    /// It exited or was killed before it got to execute the task, e.g. while waiting for its turn
+    NotReached,
+    Failed(errno::Errno),
+    Succeeded,
+}
+
+/// Kills the task once its `--timeout` is up: its process group, or its cgroup if it has one, is
+/// sent SIGTERM, and SIGKILL if it is still running `TIMEOUT_KILL_AFTER` later
+struct TimeoutKiller {
+    reaped: sync::Arc<sync::Mutex<bool>>,
+    stop: mpsc::Sender<()>,
+    thread: thread::JoinHandle<bool>,
+}
+
+impl TimeoutKiller {
+    fn start(pid: unistd::Pid, timeout: time::Duration, job_cgroup: Option<JobCgroup>) -> Self {
+        let reaped = sync::Arc::new(sync::Mutex::new(false));
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn({
+            let reaped = reaped.clone();
+            move || {
+                let send = |sig: Signal| {
+                    // Holding the lock keeps the task from being reaped, so its pid can't be reused
+                    let reaped = reaped.lock().unwrap_or_else(PoisonError::into_inner);
+                    if *reaped {
+                        return false;
+                    }
+                    match &job_cgroup {
+                        Some(job_cgroup) if sig == Signal::SIGKILL => {
+                            let _ = job_cgroup.kill();
+                        }
+                        Some(job_cgroup) => job_cgroup.signal(sig),
+                        None => {
+                            let _ = signal::killpg(pid, sig);
+                        }
+                    }
+                    true
+                };
+                let expired =
+                    |after| stopped.recv_timeout(after) == Err(mpsc::RecvTimeoutError::Timeout);
+                if !expired(timeout) || !send(Signal::SIGTERM) {
+                    return false;
+                }
+                if expired(TIMEOUT_KILL_AFTER) {
+                    send(Signal::SIGKILL);
+                }
+                true
+            }
+        });
+        TimeoutKiller {
+            reaped,
+            stop,
+            thread,
+        }
+    }
+
+    /// Called once the task exited, before it is reaped. Returns whether it timed out
+    fn stop(self) -> bool {
+        *self.reaped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        drop(self.stop);
+        self.thread.join().unwrap_or(false)
+    }
+}
+
+/// Blocks until `pid` exited, but leaves it to be reaped, so its pid stays taken
+fn wait_exited(pid: unistd::Pid) -> nix::Result<()> {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid.as_raw() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        match errno::Errno::result(result) {
+            Err(nix::Error::Sys(errno::Errno::EINTR)) => continue,
+            result => return result.map(drop),
+        }
+    }
+}
+
+/// Reaps `pid`, and returns how it exited along with the CPU time it and the children it waited
+/// for used
+fn reap(pid: unistd::Pid) -> (nix::Result<sys::wait::WaitStatus>, time::Duration) {
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    loop {
+        let result = unsafe { libc::wait4(pid.as_raw(), &mut status, 0, &mut usage) };
+        match errno::Errno::result(result) {
+            Err(nix::Error::Sys(errno::Errno::EINTR)) => continue,
+            Err(err) => return (Err(err), time::Duration::default()),
+            Ok(_) => {
+                let cpu_time = limits::cpu_time(&usage);
+                return (sys::wait::WaitStatus::from_raw(pid, status), cpu_time);
+            }
+        }
+    }
+}
+
+fn supervise(
+    fnd_cmd: ffi::OsString,
+    mut task_handler: TaskFileHandler,
+    options: QueueOptions,
+    ready_fd: RawFd,
+) -> Result<(), OpsError> {
+    // Where the task gets a cgroup of its own, if it asked for one. `queue` made sure there is one
+    let cgroup_dir = if options.cgroup.is_enabled() {
+        cgroup::cgroup_dir()
+    } else {
+        None
+    };
+    // The grandchild sends a byte right before exec, and its errno after that if exec fails. It
+    // closes by itself once the grandchild execs
+    let exec_pipe = unistd::pipe2(fcntl::OFlag::O_CLOEXEC)?;
+    // The grandchild waits for a byte on this, sent once the supervisor holds its lock
+    let supervised_pipe = unistd::pipe2(fcntl::OFlag::O_CLOEXEC)?;
+    let grandchild_fork = unsafe { unistd::fork()? };
+    match grandchild_fork {
+        unistd::ForkResult::Parent { child } => {
+            let child_pid = child.as_raw();
+            if child_pid.is_negative() {
+                return Err(OpsError::Unix(format!(
+                    "Child pid is negative {}",
+                    child_pid
+                )));
+            }
+
+            unistd::close(exec_pipe.1)?;
+            unistd::close(supervised_pipe.0)?;
+            task_handler.set_pid(child_pid as u32);
+            let task_filename = task_handler.filename();
+            // Held until this returns, so the task is only lost if this died without a status
+            let _supervisor_lock = meta::lock(&task_handler.queue_dir, &task_handler.path())
+                .path_context("lock metadata for", &task_handler.path())?;
+            let supervised = unistd::write(supervised_pipe.1, &[1])
+                .and_then(|_| unistd::close(supervised_pipe.1));
+            if let Err(err) = supervised {
+                // The task gives up without its supervisor, so nothing of it may be left behind
+                let _ = meta::remove(&task_handler.queue_dir, &task_handler.path());
+                return Err(err.into());
+            }
+            let job_cgroup = cgroup_dir
+                .as_deref()
+                .map(|dir| JobCgroup::new(dir, &task_filename));
+
+            // Consider instead of closing these, sending this output to the task file instead
+            unistd::close(io::stdin().as_raw_fd())?;
+            unistd::close(io::stdout().as_raw_fd())?;
+            unistd::close(io::stderr().as_raw_fd())?;
+
+            // Initiating process complete; drop original process
+            unistd::close(ready_fd)?;
+
+            // Returns once the task's turn has come and it was executed, or exec failed
+            let mut exec_errno = [0; 4];
+            let exec = match unistd::read(exec_pipe.0, &mut [0])? {
+                0 => Exec::NotReached,
+                _ => match unistd::read(exec_pipe.0, &mut exec_errno)? {
+                    4 => Exec::Failed(errno::Errno::from_i32(i32::from_ne_bytes(exec_errno))),
+                    _ => Exec::Succeeded,
+                },
+            };
+
+            // Failed before it was set up, in which case queueing it failed and it leaves nothing
+            if matches!(exec, Exec::NotReached) && !is_set_up(&task_handler.path()) {
+                let _ = sys::wait::waitpid(child, None);
+                match fs::remove_file(task_handler.path()) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                        return Err(err).path_context("remove", &task_handler.path())
+                    }
+                    _ => {}
+                }
+                return meta::remove(&task_handler.queue_dir, &task_handler.path())
+                    .path_context("remove metadata for", &task_handler.path());
+            }
+
+            let started_at = time::Instant::now();
+            let mut hook_env = hooks::HookEnv {
+                job_id: task_filename,
+                queue_file: task_handler.path().canonicalize()?,
+                ..hooks::HookEnv::default()
+            };
+            // Hooks are only run for tasks that were executed
+            let executed = matches!(exec, Exec::Succeeded);
+            let start_hook = if executed {
+                hooks::run(&task_handler.queue_dir, Hook::Start, None, &hook_env)
+            } else {
+                Ok(())
+            };
+
+            let timeout_killer = options
+                .timeout
+                .filter(|_| executed)
+                .map(|timeout| TimeoutKiller::start(child, timeout, job_cgroup.clone()));
+
+            // Wait for child process to finish
+            let timed_out = match timeout_killer {
+                Some(timeout_killer) => {
+                    // Should this fail, so does reaping it below, which is reported as such
+                    let _ = wait_exited(child);
+                    timeout_killer.stop()
+                }
+                None => false,
+            };
+            let (child_status, cpu_time) = reap(child);
+            hook_env.duration = Some(started_at.elapsed());
+            let killed =
+                timed_out || matches!(child_status, Ok(sys::wait::WaitStatus::Signaled(..)));
+            let usage = job_cgroup
+                .as_ref()
+                .and_then(JobCgroup::usage)
+                .unwrap_or_default();
+
+            // Cancelled by --replace, which doesn't want a trace of it left: no hooks, no status
+            // line, and the queue file goes away. A task that finished anyway is left alone
+            if meta::is_cancelled(&task_handler.queue_dir, &task_handler.path())
+                && (killed || !executed)
+            {
+                if let Some(job_cgroup) = &job_cgroup {
+                    let _ = job_cgroup.kill();
+                    job_cgroup.remove();
+                }
+                return clean::remove(&task_handler.queue_dir, &task_handler.path(), false);
+            }
+
+            let mut task_file = fs::OpenOptions::new()
+                .read(true)
+                .append(true)
+                .open(task_handler.path())
+                .path_context("open", &task_handler.path())?;
+            task_file.set_permissions(fs::Permissions::from_mode(0o600))?;
+            status::start_line(&mut task_file)?;
+
+            hook_env.exit_code = match (exec, child_status) {
+                (Exec::Failed(errno), _) => {
+                    writeln!(task_file, "[exec failed: {:?}.]", errno)?;
+                    Some(127)
+                }
+                (Exec::NotReached, Ok(sys::wait::WaitStatus::Signaled(..))) => {
+                    writeln!(task_file, "{}", status::CANCELLED)?;
+                    None
+                }
+                (_, Err(err)) => {
+                    // TODO: test this
+                    writeln!(task_file, "[child process has errored out: {}.]", err)?;
+                    None
+                }
+                (
+                    _,
+                    Ok(sys::wait::WaitStatus::Exited(..) | sys::wait::WaitStatus::Signaled(..)),
+                ) if timed_out => {
+                    let secs = options.timeout.map(timeout_secs).unwrap_or_default();
+                    writeln!(task_file, "{}{}s.]", status::TIMED_OUT_AFTER, secs)?;
+                    Some(TIMED_OUT_EXIT_CODE)
+                }
+                (_, Ok(sys::wait::WaitStatus::Exited(_, exit_code))) => {
+                    writeln!(task_file, "[exited with status {}.]", exit_code)?;
+                    Some(exit_code)
+                }
+                (_, Ok(sys::wait::WaitStatus::Signaled(_, signal, _))) => {
+                    let exceeded = options.limits.exceeded(signal, cpu_time);
+                    match exceeded.or_else(|| usage.exceeded(signal)) {
+                        Some(limit) => {
+                            writeln!(task_file, "[killed by signal: {} ({})]", signal, limit)?
+                        }
+                        None => writeln!(task_file, "[killed by signal: {}]", signal)?,
+                    }
+                    Some(128 + signal as i32)
+                }
+                (_, Ok(unknown)) => {
+                    // TODO: test this
+                    writeln!(
+                        task_file,
+                        "[child process has exited with unknown state: {:?}]",
+                        unknown
+                    )?;
+                    None
+                }
+            };
+            if let Some(memory_peak) = usage.memory_peak {
+                writeln!(task_file, "{}{} bytes.]", status::MEMORY_PEAK, memory_peak)?;
+            }
+            let succeeded = hook_env.exit_code == Some(0);
+
+            task_file.sync_all()?;
+
+            // Whatever a killed task left behind, e.g. after --timeout or fnq kill, is killed with it
+            if let (Some(job_cgroup), true) = (&job_cgroup, killed) {
+                let _ = job_cgroup.kill();
+            }
+
+            // Run before cleaning up so the queue file is still there for them to read
+            let (end_hook, end_hook_command) = if succeeded {
+                (Hook::Success, &options.on_success)
+            } else {
+                (Hook::Failure, &options.on_failure)
+            };
+            let end_hook_result = if executed {
+                hooks::run(
+                    &task_handler.queue_dir,
+                    end_hook,
+                    end_hook_command.as_deref(),
+                    &hook_env,
+                )
+            } else {
+                Ok(())
+            };
+            for (hook, result) in [(Hook::Start, start_hook), (end_hook, end_hook_result)] {
+                if let Err(err) = result {
+                    writeln!(task_file, "{}{} failed: {}.]", status::HOOK_FAILED, hook, err)?;
+                    task_file.sync_all()?;
+                }
+            }
+
+            let task_path = task_handler.path();
+            let mut cleaned = false;
+
+            if options.clean.should_clean(succeeded) {
+                match clean::remove(&task_handler.queue_dir, &task_path, options.archive) {
+                    Ok(_) => cleaned = true,
+                    Err(err) => {
+                        writeln!(task_file, "[failed to remove file: {}.]", err)?;
+                        task_file.sync_all()?;
+                    }
+                }
+            }
+
+            if options.compress && !cleaned {
+                if let Err(err) = compress::compress_in_place(&task_path) {
+                    writeln!(task_file, "[failed to compress file: {}.]", err)?;
+                    task_file.sync_all()?;
+                }
+            }
+
+            if options.keep_last.is_some() {
+                let policy = GcPolicy {
+                    keep_last: options.keep_last,
+                    archive: options.archive,
+                    ..GcPolicy::default()
+                };
+                gc(&task_handler.queue_dir, &policy)?;
+            }
+
+            // Last, as this waits for whatever the task left behind running in it
+            if let Some(job_cgroup) = job_cgroup {
+                job_cgroup.remove();
+            }
+        }
+        unistd::ForkResult::Child => {
+            unistd::close(exec_pipe.0)?;
+            unistd::close(supervised_pipe.1)?;
+            if unistd::read(supervised_pipe.0, &mut [0])? == 0 {
+                return Err(OpsError::Unknown(
+                    "Supervisor exited before the task".into(),
+                ));
+            }
+            unistd::close(supervised_pipe.0)?;
+            task_handler.set_pid(process::id());
+
+            let task_file_path = task_handler.path();
+
+            let mut task_file: fs::File = fs::OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .mode(0o600)
+                .open(&task_file_path)
+                .path_context("create", &task_file_path)?;
+
+            let task_file_descriptor = task_file.as_raw_fd();
+
+            fcntl::flock(task_file_descriptor, fcntl::FlockArg::LockExclusive)
+                .map_err(|err| OpsError::Lock(task_file_path.clone(), err.to_string()))?;
+
+            let mut argv = task_handler.args.clone();
+            argv.insert(0, task_handler.cmd.clone());
+            let mut job_meta = meta::JobMeta::current(argv, options.to_replay_args())?;
+            job_meta.name = options.name.clone();
+            job_meta.labels = options.labels.clone();
+            job_meta.batch = options.batch.clone();
+            job_meta.nice = options.nice;
+            job_meta.ionice = options.ionice;
+            meta::write(&task_handler.queue_dir, &task_file_path, &job_meta)
+                .path_context("write metadata for", &task_file_path)?;
+
+            // Quoted so the line can be pasted back into a shell to queue the task again
+            let exec_line = concat_os_strings!(
+                ffi::OsString::from("exec "),
+                shell_join(iter::once(&fnd_cmd).chain(&requeue_args(&job_meta.argv))),
+                ffi::OsString::from("\n")
+            );
+            task_file.write_all(exec_line.as_bytes())?;
+
+            // Queue file is locked so anything queued after this will wait on it
+            unistd::write(ready_fd, task_handler.filename().as_bytes())?;
+            unistd::close(ready_fd)?;
+
+            unistd::dup2(task_file_descriptor, io::stdout().as_raw_fd())?;
+            unistd::dup2(task_file_descriptor, io::stderr().as_raw_fd())?;
+
+            for entry in files::files(&task_handler.queue_dir)? {
+                // Files are in the order they were queued in. Waiting on one queued after this
+                // one would deadlock with it, as it waits on this one in turn
+                if entry.filepath == task_file_path {
+                    break;
+                }
+
+                block_on_locked_file(&entry.filepath)?;
+            }
+            // Before taking the locks, so waiting for memory or load doesn't keep them from others
+            admission::wait_for_resources(options.min_memory, options.max_load)?;
+            // Still shows as waiting until it holds them. Kept open until exec
+            let _held_locks = locks::acquire(&options.locks)?;
+            // Cancelled by --replace while it waited, so its supervisor removes it
+            if meta::is_cancelled(&task_handler.queue_dir, &task_file_path) {
+                return Ok(());
+            }
+
+            writeln!(task_file)?;
+
+            task_file.set_permissions(fs::Permissions::from_mode(0o700))?;
+
+            if task_handler.cmd == BARRIER_ARG {
+                // Counts as executed, and exiting closes the exec pipe the same way exec would
+                unistd::write(exec_pipe.1, &[1])?;
+                let all_succeeded =
+                    barrier::check(&task_handler.queue_dir, &task_file_path, &mut task_file)?;
+                process::exit(if all_succeeded { 0 } else { 1 });
+            }
+
+            let task_filename = task_handler.filename();
+
+            let cmd_c: ffi::CString = ffi::CString::new(task_handler.cmd.as_os_str().as_bytes())?;
+            task_handler.args.insert(0, task_handler.cmd);
+            let args_c = task_handler
+                .args
+                .iter()
+                .map(|arg| ffi::CString::new(arg.as_os_str().as_bytes()))
+                .collect::<Result<Vec<ffi::CString>, ffi::NulError>>()?;
+
+            env::set_var("FNQJOBID", &task_filename);
+            unistd::setsid()?;
+            if let Some(cgroup_dir) = &cgroup_dir {
+                JobCgroup::new(cgroup_dir, &task_filename).enter(&options.cgroup)?;
+            }
+            options.limits.apply()?;
+            priority::apply(options.nice, options.ionice)?;
+            unistd::write(exec_pipe.1, &[1])?;
+            let Err(err) = unistd::execvp(&cmd_c, &args_c);
+            let exec_errno = err.as_errno().unwrap_or(errno::Errno::UnknownErrno);
+            unistd::write(exec_pipe.1, &(exec_errno as i32).to_ne_bytes())?;
+            process::exit(127);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::prelude::*;
+use std::{env, ffi, fs, io, path, process};
+
+use nix::fcntl;
+
+use crate::cgroup::{self, JobCgroup};
+use crate::{clean, files, is_locked, meta, open_file, queue, status, OpsError};
+
+pub struct Repaired {
+    pub filepath: path::PathBuf,
+    /// Queue file of the task enqueued in place of the lost one
+    pub requeued: Option<ffi::OsString>,
+    /// Why the lost task could not be queued again. It is still marked lost, so that a later
+    /// `--resume` can retry it
+    pub requeue_error: Option<OpsError>,
+}
+
+// Lost tasks have no status line. Ones that are already marked lost are picked up again if they
+// still have to be requeued
+fn needs_repair(opened_file: &mut fs::File, requeue_lost: bool) -> Result<bool, OpsError> {
+    match status::read_status(opened_file)? {
+        None => Ok(true),
+        Some(status::JobStatus::SupervisorLost) if requeue_lost => {
+            Ok(!status::is_requeued(opened_file)?)
+        }
+        Some(_) => Ok(false),
+    }
+}
+
+fn requeue(
+    queue_dir: &path::Path,
+    filepath: &path::Path,
+    header: &status::JobHeader,
+) -> Result<Option<ffi::OsString>, OpsError> {
+    let mut command = process::Command::new(env::current_exe()?);
+
+    match meta::read(queue_dir, filepath)? {
+        Some(job_meta) => {
+            command
+                .args(job_meta.options)
+                .args(queue::requeue_args(&job_meta.argv))
+                .current_dir(job_meta.cwd)
+                .env_clear()
+                .envs(job_meta.env);
+        }
+        // Queued by an older fnq, so the exec line is all there is to go on
+        None => match header.exec_args() {
+            Some(exec_args) if exec_args.len() > 1 => {
+                command.args(&exec_args[1..]);
+            }
+            _ => return Ok(None),
+        },
+    }
+
+    // The task's cwd may differ from ours, so the queue dir has to be absolute
+    let output = command
+        .env("FNQ_DIR", queue_dir.canonicalize()?)
+        .stdin(process::Stdio::null())
+        .stderr(process::Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(OpsError::Unknown(format!(
+            "Could not requeue {:?}",
+            filepath
+        )));
+    }
+
+    let requeued = output.stdout.trim_ascii_end().to_vec();
+    Ok(Some(ffi::OsString::from_vec(requeued)))
+}
+
+/// Finds queue files whose supervising process died before writing a status line (e.g. OOM or
+/// a reboot) and marks them with `[supervisor lost.]`. If `requeue_lost` is set, also queues every
+/// lost task that wasn't already queued again, in order, with the arguments, working directory,
+/// environment and flags it was originally queued with. A task that fails to be queued again
+/// doesn't keep the others from it
+pub fn repair(queue_dir: &path::PathBuf, requeue_lost: bool) -> Result<Vec<Repaired>, OpsError> {
+    let mut repaired = Vec::new();
+    for entry in files::files(queue_dir)? {
+        let filepath = entry.filepath;
+        let mut opened_file = match open_file(&filepath) {
+            // Removed (e.g. by --clean) since it was listed
+            Err(OpsError::PathIO(_, _, err)) if err.kind() == io::ErrorKind::NotFound => continue,
+            result => result?,
+        };
+        let fd = opened_file.as_raw_fd();
+
+        // A supervisor holds its lock until it has written the status line, so it has to be gone
+        // before a missing one means anything. Holding the queue file's lock keeps concurrent
+        // repairs from marking (and requeueing) it twice
+        if is_locked(fd)?
+            || meta::is_locked(queue_dir, &filepath)?
+            || fcntl::flock(fd, fcntl::FlockArg::LockExclusiveNonblock).is_err()
+            || !needs_repair(&mut opened_file, requeue_lost)?
+        {
+            continue;
+        }
+
+        // Its supervisor died before it could remove it for --replace
+        if meta::is_cancelled(queue_dir, &filepath) {
+            match clean::remove(queue_dir, &filepath, false) {
+                Err(OpsError::PathIO(_, _, err)) if err.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+            continue;
+        }
+
+        if status::read_status(&mut opened_file)?.is_none() {
+            status::start_line(&mut opened_file)?;
+            opened_file.seek(SeekFrom::End(0))?;
+            writeln!(opened_file, "{}", status::SUPERVISOR_LOST)?;
+            opened_file.set_permissions(fs::Permissions::from_mode(0o600))?;
+            // Its supervisor would have removed the task's cgroup
+            if let (Some(cgroup_dir), Some(filename)) = (cgroup::cgroup_dir(), filepath.file_name())
+            {
+                JobCgroup::new(&cgroup_dir, filename).remove_if_empty();
+            }
+        }
+
+        let mut requeued = None;
+        let mut requeue_error = None;
+        if requeue_lost {
+            let header = status::read_header(opened_file.try_clone()?)?;
+            match requeue(queue_dir, &filepath, &header) {
+                Ok(task_filename) => requeued = task_filename,
+                Err(err) => requeue_error = Some(err),
+            }
+            if let Some(requeued) = &requeued {
+                opened_file.seek(SeekFrom::End(0))?;
+                writeln!(
+                    opened_file,
+                    "{}{}.]",
+                    status::REQUEUED_AS,
+                    requeued.to_string_lossy()
+                )?;
+            }
+        }
+
+        opened_file.sync_all()?;
+        fcntl::flock(fd, fcntl::FlockArg::Unlock)?;
+        repaired.push(Repaired {
+            filepath,
+            requeued,
+            requeue_error,
+        });
+    }
+
+    Ok(repaired)
+}
@@ -0,0 +1,31 @@
+use std::os::unix::prelude::*;
+use std::path;
+
+use nix::fcntl;
+
+use crate::{clean, files, open_file, OpsError};
+
+/// Removes finished queue files along with their meta files. Fails on the first one that is
+/// running or waiting to run, or that isn't a queue file in `queue_dir`
+pub fn rm(queue_dir: &path::PathBuf, queue_paths: &[path::PathBuf]) -> Result<(), OpsError> {
+    let queue_files = files::files(queue_dir)?;
+    for queue_path in queue_paths {
+        if !queue_files.iter().any(|entry| entry.filepath == *queue_path) {
+            return Err(OpsError::FileNotFound(queue_path.into()));
+        }
+        let opened_file = open_file(queue_path)?;
+        // Running and waiting tasks hold a lock on their queue file
+        if fcntl::flock(
+            opened_file.as_raw_fd(),
+            fcntl::FlockArg::LockExclusiveNonblock,
+        )
+        .is_err()
+        {
+            return Err(OpsError::JobRunning(queue_path.clone()));
+        }
+
+        clean::remove(queue_dir, queue_path, false)?;
+    }
+
+    Ok(())
+}
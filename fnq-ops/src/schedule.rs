@@ -0,0 +1,336 @@
+use std::io::{BufRead, Read, Write};
+use std::os::unix::prelude::*;
+use std::{ffi, fs, io, path, process, thread, time};
+
+use nix::{fcntl, sys, unistd};
+
+use crate::os_strings::shell_join;
+use crate::queue::{close_inherited_fds, exit_forked, find_executable};
+use crate::{is_locked, job, queue, Cron, IOResultExt, JobState, OpsError, QueueOptions};
+
+/// Subdirectory of the queue dir holding a file per schedule. The scheduler keeps it locked while
+/// it runs and appends a line for every firing
+pub const SCHEDULES_DIR: &str = "schedules";
+
+// How soon the scheduler notices that its schedule file was removed
+const REMOVED_POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    /// Right away, and then every so often
+    Every(time::Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// The fnq flag that would parse back into this schedule
+    pub fn to_args(&self) -> Vec<ffi::OsString> {
+        match self {
+            Schedule::Every(interval) => vec![
+                "--every".into(),
+                format!("{}s", interval.as_secs().max(1)).into(),
+            ],
+            Schedule::Cron(cron) => vec!["--cron".into(), cron.to_string().into()],
+        }
+    }
+
+    fn first(&self, now: time::SystemTime) -> Option<time::SystemTime> {
+        match self {
+            Schedule::Every(_) => Some(now),
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+
+    /// Firings missed while the machine was asleep are skipped rather than caught up on
+    fn next(&self, fired: time::SystemTime, now: time::SystemTime) -> Option<time::SystemTime> {
+        match self {
+            Schedule::Every(interval) => {
+                let mut next = fired + *interval;
+                while next <= now {
+                    next += *interval;
+                }
+                Some(next)
+            }
+            Schedule::Cron(cron) => cron.next_after(fired.max(now)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ScheduleEntry {
+    pub id: ffi::OsString,
+    /// Whether its scheduler is still running. It doesn't survive a reboot
+    pub active: bool,
+    /// Shell quoted `--every` or `--cron` flag
+    pub schedule: ffi::OsString,
+    /// Shell quoted task command line
+    pub command: ffi::OsString,
+    /// Queue file of the most recent firing, if any
+    pub last_queued: Option<ffi::OsString>,
+}
+
+fn schedules_dir(queue_dir: &path::Path) -> path::PathBuf {
+    queue_dir.join(SCHEDULES_DIR)
+}
+
+fn new_schedule_id() -> Result<String, OpsError> {
+    let millis = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)?
+        .as_millis();
+    Ok(format!("schedule{}.{}", millis, process::id()))
+}
+
+/// Starts a detached scheduler that queues the task on `schedule` until its schedule file is
+/// removed, and returns the schedule's id. A firing is skipped while the task it queued last is
+/// still waiting or running
+pub fn schedule(
+    fnd_cmd: ffi::OsString,
+    task_cmd: ffi::OsString,
+    task_args: Vec<ffi::OsString>,
+    queue_dir: path::PathBuf,
+    options: QueueOptions,
+    schedule: Schedule,
+) -> Result<ffi::OsString, OpsError> {
+    find_executable(&task_cmd)?;
+    let dir = schedules_dir(&queue_dir);
+    fs::create_dir_all(&dir).path_context("create", &dir)?;
+
+    let scheduler = Scheduler {
+        fnd_cmd,
+        task_cmd,
+        task_args,
+        queue_dir,
+        options,
+        schedule,
+    };
+    // Sends the schedule id once the scheduler has locked its schedule file
+    let pipe = unistd::pipe()?;
+    match unsafe { unistd::fork()? } {
+        unistd::ForkResult::Parent { child } => {
+            unistd::close(pipe.1)?;
+            let mut ready = unsafe { fs::File::from_raw_fd(pipe.0) };
+            sys::wait::waitpid(child, None)?;
+
+            let mut id = Vec::new();
+            ready.read_to_end(&mut id)?;
+            if id.is_empty() {
+                return Err(OpsError::Unknown("Task could not be scheduled".into()));
+            }
+            Ok(ffi::OsString::from_vec(id))
+        }
+        unistd::ForkResult::Child => {
+            let result = unistd::close(pipe.0)
+                .map_err(OpsError::from)
+                .and_then(|_| close_inherited_fds(pipe.1))
+                .and_then(|_| {
+                    unistd::setsid()?;
+                    if let unistd::ForkResult::Child = unsafe { unistd::fork()? } {
+                        exit_forked(scheduler.run(pipe.1));
+                    }
+                    Ok(())
+                });
+            exit_forked(result)
+        }
+    }
+}
+
+struct Scheduler {
+    fnd_cmd: ffi::OsString,
+    task_cmd: ffi::OsString,
+    task_args: Vec<ffi::OsString>,
+    queue_dir: path::PathBuf,
+    options: QueueOptions,
+    schedule: Schedule,
+}
+
+impl Scheduler {
+    fn run(self, ready_fd: RawFd) -> Result<(), OpsError> {
+        let id = new_schedule_id()?;
+        let schedule_path = schedules_dir(&self.queue_dir).join(&id);
+        let mut schedule_file = fs::OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .mode(0o600)
+            .open(&schedule_path)
+            .path_context("create", &schedule_path)?;
+        fcntl::flock(schedule_file.as_raw_fd(), fcntl::FlockArg::LockExclusive)
+            .map_err(|err| OpsError::Lock(schedule_path.clone(), err.to_string()))?;
+
+        let argv: Vec<&ffi::OsString> = Some(&self.task_cmd)
+            .into_iter()
+            .chain(&self.task_args)
+            .collect();
+        for (key, value) in [
+            ("schedule", shell_join(&self.schedule.to_args())),
+            ("command", shell_join(argv)),
+            ("options", shell_join(&self.options.to_args())),
+        ] {
+            schedule_file.write_all(
+                concat_os_strings!(
+                    ffi::OsStr::new(key),
+                    ffi::OsStr::new(" "),
+                    value,
+                    ffi::OsStr::new("\n")
+                )
+                .as_bytes(),
+            )?;
+        }
+        writeln!(schedule_file)?;
+
+        unistd::write(ready_fd, id.as_bytes())?;
+        unistd::close(ready_fd)?;
+        // Nobody reads the scheduler's output, and the tasks it queues write to their queue files
+        let dev_null = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        for stdio in 0..=2 {
+            unistd::dup2(dev_null.as_raw_fd(), stdio)?;
+        }
+
+        let mut last_queued: Option<ffi::OsString> = None;
+        let mut next = self.schedule.first(time::SystemTime::now());
+        while let Some(fire_at) = next {
+            loop {
+                if !schedule_path.exists() {
+                    return Ok(());
+                }
+                match fire_at.duration_since(time::SystemTime::now()) {
+                    Ok(remaining) if !remaining.is_zero() => {
+                        thread::sleep(remaining.min(REMOVED_POLL_INTERVAL))
+                    }
+                    _ => break,
+                }
+            }
+
+            let pending = last_queued.as_ref().is_some_and(|queue_file| {
+                matches!(
+                    job(self.queue_dir.join(queue_file)).map(|job| job.state),
+                    Ok(JobState::Waiting) | Ok(JobState::Running)
+                )
+            });
+            let event = if pending {
+                concat_os_strings!(
+                    ffi::OsStr::new("skipped\t"),
+                    last_queued.clone().unwrap_or_default()
+                )
+            } else {
+                match queue(
+                    self.fnd_cmd.clone(),
+                    self.task_cmd.clone(),
+                    self.task_args.clone(),
+                    self.queue_dir.clone(),
+                    self.options.clone(),
+                ) {
+                    Ok(queue_file) => {
+                        last_queued = Some(queue_file.clone());
+                        concat_os_strings!(ffi::OsStr::new("queued\t"), queue_file)
+                    }
+                    Err(err) => format!("error\t{}", err).into(),
+                }
+            };
+            let now = time::SystemTime::now();
+            let unix_time = now.duration_since(time::UNIX_EPOCH)?.as_secs();
+            schedule_file.write_all(
+                concat_os_strings!(
+                    ffi::OsString::from(format!("{}\t", unix_time)),
+                    event,
+                    ffi::OsStr::new("\n")
+                )
+                .as_bytes(),
+            )?;
+
+            next = self.schedule.next(fire_at, now);
+        }
+        Ok(())
+    }
+}
+
+fn read_schedule(schedule_path: &path::Path) -> Result<ScheduleEntry, OpsError> {
+    let schedule_file = fs::File::open(schedule_path).path_context("open", schedule_path)?;
+    let mut entry = ScheduleEntry {
+        id: schedule_path.file_name().unwrap_or_default().into(),
+        active: is_locked(schedule_file.as_raw_fd())?,
+        schedule: ffi::OsString::new(),
+        command: ffi::OsString::new(),
+        last_queued: None,
+    };
+    for line in io::BufReader::new(schedule_file).split(b'\n') {
+        let line = line?;
+        if let Some(schedule) = line.strip_prefix(b"schedule ") {
+            entry.schedule = ffi::OsStr::from_bytes(schedule).into();
+        } else if let Some(command) = line.strip_prefix(b"command ") {
+            entry.command = ffi::OsStr::from_bytes(command).into();
+        } else if let [_, b"queued", queue_file] =
+            line.split(|&byte| byte == b'\t').collect::<Vec<_>>()[..]
+        {
+            entry.last_queued = Some(ffi::OsStr::from_bytes(queue_file).into());
+        }
+    }
+    Ok(entry)
+}
+
+/// Every schedule of the queue dir, oldest first
+pub fn schedules(queue_dir: &path::Path) -> Result<Vec<ScheduleEntry>, OpsError> {
+    let dir = schedules_dir(queue_dir);
+    let dir_entries = match fs::read_dir(&dir) {
+        Ok(dir_entries) => dir_entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(OpsError::PathIO("read schedules dir", dir, err)),
+    };
+
+    let mut schedule_paths = dir_entries
+        .map(|dir_entry| dir_entry.map(|dir_entry| dir_entry.path()))
+        .collect::<Result<Vec<path::PathBuf>, io::Error>>()
+        .path_context("read schedules dir", &dir)?;
+    // Ids start with the time they were created at in millis, which has the same number of
+    // digits for a long while yet
+    schedule_paths.sort();
+
+    schedule_paths
+        .iter()
+        .map(|schedule_path| read_schedule(schedule_path))
+        .collect()
+}
+
+/// Removes schedules, which stops their schedulers from queueing anything else. Tasks they
+/// already queued are left alone
+pub fn unschedule(queue_dir: &path::Path, ids: &[ffi::OsString]) -> Result<(), OpsError> {
+    let dir = schedules_dir(queue_dir);
+    let schedule_paths: Vec<path::PathBuf> = ids.iter().map(|id| dir.join(id)).collect();
+    for (id, schedule_path) in ids.iter().zip(&schedule_paths) {
+        if id.as_bytes().contains(&b'/') || !schedule_path.is_file() {
+            return Err(OpsError::ScheduleNotFound(id.clone()));
+        }
+    }
+    for schedule_path in schedule_paths {
+        fs::remove_file(&schedule_path).path_context("remove", &schedule_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule_next() {
+        let start = time::UNIX_EPOCH + time::Duration::from_secs(1_700_000_000);
+        let minute = time::Duration::from_secs(60);
+        let every = Schedule::Every(minute);
+        assert_eq!(every.first(start), Some(start));
+        assert_eq!(every.next(start, start), Some(start + minute));
+        // Woke up from sleep 3.5 minutes later
+        assert_eq!(
+            every.next(start, start + minute * 7 / 2),
+            Some(start + minute * 4)
+        );
+        assert_eq!(every.to_args(), vec!["--every", "60s"]);
+
+        let cron = Schedule::Cron(Cron::parse("*/5 * * * *").unwrap());
+        let first = cron.first(start).unwrap();
+        assert!(first > start);
+        assert_eq!(cron.next(first, first), Some(first + minute * 5));
+        assert_eq!(cron.to_args(), vec!["--cron", "*/5 * * * *"]);
+    }
+}
@@ -0,0 +1,266 @@
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::os::unix::prelude::*;
+use std::{ffi, fmt, fs, io};
+
+use crate::queue::END_OF_OPTIONS;
+use crate::{barrier::BARRIER_ARG, compress, os_strings};
+
+// Status lines are short; only the tail of a queue file needs to be read to find one
+const STATUS_TAIL_LEN: u64 = 4096;
+
+/// Status line appended by `--repair` when the supervising process died without writing one
+pub const SUPERVISOR_LOST: &str = "[supervisor lost.]";
+
+/// Status line of a task that was killed before its command was executed, e.g. by `fnq kill`
+/// while it waited for its turn, or that `--replace` cancelled
+pub const CANCELLED: &str = "[cancelled.]";
+
+/// Prefix of the status line of a task its supervisor killed once its `--timeout` was up
+pub const TIMED_OUT_AFTER: &str = "[timed out after ";
+
+/// Prefix of the line appended by `--resume` once a lost task was queued again
+pub const REQUEUED_AS: &str = "[requeued as ";
+
+/// Prefix of the line appended by the supervisor when a hook fails
+pub const HOOK_FAILED: &str = "[hook ";
+
+/// Prefix of the line appended by the supervisor with the most memory the task's cgroup used
+pub const MEMORY_PEAK: &str = "[memory peak: ";
+
+// Lines appended after the status line that don't replace it
+const ANNOTATIONS: [&str; 5] = [
+    MEMORY_PEAK,
+    "[failed to remove file",
+    "[failed to compress file",
+    REQUEUED_AS,
+    HOOK_FAILED,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Exited(i32),
+    Killed(String),
+    ExecFailed(String),
+    SupervisorLost,
+    Cancelled,
+    /// Killed once it had run for this many seconds
+    TimedOut(u64),
+    Other(String),
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStatus::Exited(exit_code) => write!(f, "exited {}", exit_code),
+            JobStatus::Killed(signal) => write!(f, "killed {}", signal),
+            JobStatus::ExecFailed(errno) => write!(f, "exec failed {}", errno),
+            JobStatus::SupervisorLost => write!(f, "supervisor lost"),
+            JobStatus::Cancelled => write!(f, "cancelled"),
+            JobStatus::TimedOut(secs) => write!(f, "timed out after {}s", secs),
+            JobStatus::Other(status) => write!(f, "{}", status),
+        }
+    }
+}
+
+impl JobStatus {
+    pub fn succeeded(&self) -> bool {
+        *self == JobStatus::Exited(0)
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        // Output that didn't end with a newline puts the status line at the end of its last line
+        let line = &line[line.rfind('[')?..];
+        if line == SUPERVISOR_LOST {
+            return Some(JobStatus::SupervisorLost);
+        }
+        if line == CANCELLED {
+            return Some(JobStatus::Cancelled);
+        }
+        let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+        if let Some(code) = inner.strip_prefix("exited with status ") {
+            return code
+                .trim_end_matches('.')
+                .parse()
+                .ok()
+                .map(JobStatus::Exited);
+        }
+        if let Some(signal) = inner.strip_prefix("killed by signal: ") {
+            return Some(JobStatus::Killed(signal.into()));
+        }
+        if let Some(secs) = line.strip_prefix(TIMED_OUT_AFTER) {
+            return secs
+                .strip_suffix("s.]")?
+                .parse()
+                .ok()
+                .map(JobStatus::TimedOut);
+        }
+        if let Some(errno) = inner.strip_prefix("exec failed: ") {
+            return Some(JobStatus::ExecFailed(errno.trim_end_matches('.').into()));
+        }
+        if inner.starts_with("child process has") {
+            return Some(JobStatus::Other(inner.into()));
+        }
+        None
+    }
+}
+
+fn read_tail(file: &mut fs::File) -> Result<String, io::Error> {
+    let mut tail = Vec::new();
+    if compress::is_compressed(file)? {
+        // Compressed files can't be seeked into so the whole file has to be decompressed
+        compress::reader(file.try_clone()?)?.read_to_end(&mut tail)?;
+        tail.drain(..tail.len().saturating_sub(STATUS_TAIL_LEN as usize));
+    } else {
+        let len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(STATUS_TAIL_LEN)))?;
+        file.read_to_end(&mut tail)?;
+    }
+    Ok(String::from_utf8_lossy(&tail).into_owned())
+}
+
+/// Reads the status line written by the supervising process once the task has exited. Returns
+/// `None` if the task has not finished (or its supervisor never got to write one)
+pub fn read_status(file: &mut fs::File) -> Result<Option<JobStatus>, io::Error> {
+    let status = read_tail(file)?
+        .lines()
+        .rev()
+        .find(|line| {
+            !ANNOTATIONS
+                .iter()
+                .any(|annotation| line.starts_with(annotation))
+        })
+        .and_then(JobStatus::parse);
+
+    Ok(status)
+}
+
+/// Makes sure whatever gets appended next starts on its own line, even if the task's output
+/// didn't end with a newline
+pub fn start_line(file: &mut fs::File) -> Result<(), io::Error> {
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(());
+    }
+
+    let mut last = [0; 1];
+    file.seek(SeekFrom::Start(len - 1))?;
+    file.read_exact(&mut last)?;
+    if last != *b"\n" {
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Determines if `--resume` already queued a lost task again
+pub fn is_requeued(file: &mut fs::File) -> Result<bool, io::Error> {
+    Ok(read_tail(file)?
+        .lines()
+        .rev()
+        .take(ANNOTATIONS.len() + 1)
+        .any(|line| line.starts_with(REQUEUED_AS)))
+}
+
+/// Header of a queue file: the lines written before the task started running
+#[derive(Debug, PartialEq)]
+pub struct JobHeader {
+    pub lines: Vec<ffi::OsString>,
+    /// The header is terminated by an empty line once the task's turn in the queue has come
+    pub started: bool,
+}
+
+impl JobHeader {
+    /// Arguments recorded by the `exec` header line, starting with the fnq command that queued
+    /// the task
+    pub fn exec_args(&self) -> Option<Vec<ffi::OsString>> {
+        let exec = self
+            .lines
+            .iter()
+            .find_map(|line| line.as_bytes().strip_prefix(b"exec "))?;
+        os_strings::shell_split(ffi::OsStr::from_bytes(exec))
+    }
+
+    /// The shell quoted task command line, without the fnq command that queued it or the `--`
+    /// that ends its options
+    pub fn command(&self) -> Option<ffi::OsString> {
+        let exec_args = self.exec_args()?;
+        let argv = exec_args.get(1..)?;
+        let argv = argv.strip_prefix(&[END_OF_OPTIONS.into()]).unwrap_or(argv);
+        Some(os_strings::shell_join(argv))
+    }
+
+    /// Whether the queue file is a marker queued with `--barrier` rather than a task
+    pub fn is_barrier(&self) -> bool {
+        self.exec_args()
+            .is_some_and(|exec_args| exec_args.get(1..) == Some(&[BARRIER_ARG.into()]))
+    }
+}
+
+pub fn read_header(file: fs::File) -> Result<JobHeader, io::Error> {
+    let mut header = JobHeader {
+        lines: Vec::new(),
+        started: false,
+    };
+    for line in io::BufReader::new(compress::reader(file)?).split(b'\n') {
+        let line = line?;
+        if line.is_empty() {
+            header.started = true;
+            break;
+        }
+        header.lines.push(ffi::OsString::from_vec(line));
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(
+            JobStatus::parse("[exited with status 0.]"),
+            Some(JobStatus::Exited(0))
+        );
+        assert_eq!(
+            JobStatus::parse("[exited with status 127.]"),
+            Some(JobStatus::Exited(127))
+        );
+        assert_eq!(
+            JobStatus::parse("[killed by signal: SIGTERM]"),
+            Some(JobStatus::Killed("SIGTERM".into()))
+        );
+        assert_eq!(
+            JobStatus::parse("[killed by signal: SIGXCPU (exceeded --limit-cpu 10s)]"),
+            Some(JobStatus::Killed("SIGXCPU (exceeded --limit-cpu 10s)".into()))
+        );
+        assert_eq!(
+            JobStatus::parse("[killed by signal: SIGKILL (out of memory)]"),
+            Some(JobStatus::Killed("SIGKILL (out of memory)".into()))
+        );
+        assert_eq!(
+            JobStatus::parse("[exec failed: ENOENT.]"),
+            Some(JobStatus::ExecFailed("ENOENT".into()))
+        );
+        assert_eq!(
+            JobStatus::parse(SUPERVISOR_LOST),
+            Some(JobStatus::SupervisorLost)
+        );
+        assert_eq!(JobStatus::parse(CANCELLED), Some(JobStatus::Cancelled));
+        assert_eq!(
+            JobStatus::parse("[timed out after 90s.]"),
+            Some(JobStatus::TimedOut(90))
+        );
+        assert_eq!(JobStatus::TimedOut(90).to_string(), "timed out after 90s");
+        assert_eq!(
+            JobStatus::parse("no newline at the end[exited with status 3.]"),
+            Some(JobStatus::Exited(3))
+        );
+        assert_eq!(JobStatus::parse("exited with status 0."), None);
+        assert_eq!(JobStatus::parse("[something the task printed]"), None);
+        assert!(JobStatus::Exited(0).succeeded());
+        assert!(!JobStatus::Exited(1).succeeded());
+        assert!(!JobStatus::Killed("SIGKILL".into()).succeeded());
+        assert!(!JobStatus::TimedOut(1).succeeded());
+    }
+}
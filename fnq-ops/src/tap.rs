@@ -1,12 +1,12 @@
 use std::os::unix::prelude::*;
-use std::path;
+use std::{ffi, io, path};
 
 use nix::fcntl;
 
-use crate::ops::{files, open_file, OpsError};
+use crate::{files, is_locked, list, open_file, OpsError};
 
 pub fn tap(queue_dir: &path::PathBuf, queue_file: Option<path::PathBuf>) -> Result<bool, OpsError> {
-    let queue_files = files::files(&queue_dir)?;
+    let queue_files = files::files(queue_dir)?;
 
     if let Some(queue_file) = queue_file {
         let entry = queue_files
@@ -14,9 +14,7 @@ pub fn tap(queue_dir: &path::PathBuf, queue_file: Option<path::PathBuf>) -> Resu
             .find(|&entry| entry.filepath.eq(&queue_file));
 
         match entry {
-            None => {
-                return Err(OpsError::FileNotFound(queue_file.into()))
-            }
+            None => return Err(OpsError::FileNotFound(queue_file.into())),
             Some(entry) => {
                 let opened_file = open_file(&entry.filepath)?;
                 let fd: RawFd = opened_file.as_raw_fd();
@@ -43,3 +41,19 @@ pub fn tap(queue_dir: &path::PathBuf, queue_file: Option<path::PathBuf>) -> Resu
 
     Ok(false)
 }
+
+/// Whether any job queued with `--batch <batch>` is still running or waiting to run
+pub fn tap_batch(queue_dir: &path::PathBuf, batch: &ffi::OsStr) -> Result<bool, OpsError> {
+    for queue_path in list::batch_files(queue_dir, batch)? {
+        let opened_file = match open_file(&queue_path) {
+            // Removed (e.g. by --clean) since it was listed
+            Err(OpsError::PathIO(_, _, err)) if err.kind() == io::ErrorKind::NotFound => continue,
+            result => result?,
+        };
+        if is_locked(opened_file.as_raw_fd())? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
@@ -0,0 +1,93 @@
+use std::os::unix::prelude::*;
+use std::task::Poll;
+use std::{ffi, fs, io, path, thread, time};
+
+use crate::{block_on_locked_file, files, is_locked, list, meta, status, IOResultExt, OpsError};
+
+/// Checks whether the task of a queue file has finished without blocking, for callers that poll
+pub(crate) struct StatusWaiter {
+    queue_path: path::PathBuf,
+}
+
+impl StatusWaiter {
+    pub fn new(queue_path: path::PathBuf) -> Self {
+        Self { queue_path }
+    }
+
+    /// Returns the status once the task has finished. It is `None` if the queue file was removed
+    /// (e.g. by --clean) or its supervisor died without writing a status line
+    pub fn poll_status(&mut self) -> Result<Poll<Option<status::JobStatus>>, OpsError> {
+        let mut queue_file = match fs::File::open(&self.queue_path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Poll::Ready(None)),
+            queue_file => queue_file.path_context("open", &self.queue_path)?,
+        };
+        if is_locked(queue_file.as_raw_fd())? {
+            return Ok(Poll::Pending);
+        }
+
+        // The supervisor appends the status line only after the task released its lock, and
+        // holds a lock of its own until then
+        let supervised = match self.queue_path.parent() {
+            Some(queue_dir) => meta::is_locked(queue_dir, &self.queue_path)?,
+            None => false,
+        };
+        match status::read_status(&mut queue_file)? {
+            Some(job_status) => Ok(Poll::Ready(Some(job_status))),
+            None if supervised => Ok(Poll::Pending),
+            None => Ok(Poll::Ready(None)),
+        }
+    }
+}
+
+/// Blocks until the task of a queue file has finished and returns its status. See
+/// `StatusWaiter::poll_status`
+pub fn wait_for_status(queue_path: &path::PathBuf) -> Result<Option<status::JobStatus>, OpsError> {
+    block_on_locked_file(queue_path)?;
+
+    let mut waiter = StatusWaiter::new(queue_path.clone());
+    loop {
+        if let Poll::Ready(job_status) = waiter.poll_status()? {
+            return Ok(job_status);
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+}
+
+pub fn block(queue_dir: path::PathBuf, queue_file: Option<path::PathBuf>) -> Result<(), OpsError> {
+    if let Some(queue_file) = queue_file {
+        // Not looked up in the queue dir again, as it may have been removed (e.g. by its own
+        // --clean) since it was found, which counts as finished
+        match wait_for_status(&queue_file)? {
+            Some(job_status) if !job_status.succeeded() => {
+                return Err(OpsError::JobFailed(queue_file, job_status.to_string()))
+            }
+            _ => {}
+        }
+    } else {
+        for entry in files::files(&queue_dir)? {
+            // Tasks are only done once their status line is written
+            wait_for_status(&entry.filepath)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Blocks until every job queued with `--batch <batch>` has finished. Fails with the first one
+/// that did not succeed, once all of them are done
+pub fn block_batch(queue_dir: path::PathBuf, batch: &ffi::OsStr) -> Result<(), OpsError> {
+    let mut failed = None;
+    for queue_path in list::batch_files(&queue_dir, batch)? {
+        match wait_for_status(&queue_path)? {
+            Some(job_status) if !job_status.succeeded() && failed.is_none() => {
+                failed = Some(OpsError::JobFailed(queue_path, job_status.to_string()))
+            }
+            _ => {}
+        }
+    }
+
+    match failed {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
@@ -3,18 +3,7 @@ use std::{fs, io, path};
 
 use nix::fcntl;
 
-use crate::ops::{files, OpsError};
-
-fn is_locked(raw_fd: RawFd) -> Result<bool, OpsError> {
-    match fcntl::flock(raw_fd, fcntl::FlockArg::LockSharedNonblock) {
-        Ok(_) => {
-            fcntl::flock(raw_fd, fcntl::FlockArg::Unlock)?;
-            Ok(false)
-        }
-        Err(nix::Error::Sys(nix::errno::EWOULDBLOCK)) => Ok(true),
-        Err(err) => Err(OpsError::from(err)),
-    }
-}
+use crate::{files, is_locked, OpsError};
 
 pub fn watch(queue_dir: path::PathBuf) -> Result<(), OpsError> {
     let queue_files = files::files(&queue_dir)?;
@@ -53,7 +42,7 @@ pub fn watch(queue_dir: path::PathBuf) -> Result<(), OpsError> {
                             }
                             Op::CLOSE_WRITE => {
                                 io::copy(&mut queue_file, &mut io::stdout())?;
-                                close_count = close_count + 1;
+                                close_count += 1;
                                 queue_file.sync_all()?;
                             }
                             Op::RENAME => {
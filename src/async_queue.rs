@@ -0,0 +1,88 @@
+use std::future::Future;
+use std::io::Read;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{io, mem, path};
+
+use futures_core::Stream;
+use tokio::task::{self, JoinHandle};
+
+use crate::ops::{self, JobStatus, OpsError};
+use crate::{JobId, Queue};
+
+// Output is yielded in chunks of at most this many bytes
+const CHUNK_LEN: usize = 8192;
+
+impl Queue {
+    /// Same as `wait`, but blocks a thread of tokio's blocking pool rather than the caller's. The
+    /// thread is only given back once the task has finished, even if the future is dropped
+    pub async fn wait_async(&self, id: &JobId) -> Result<Option<JobStatus>, OpsError> {
+        let job_path = self.job_path(id)?;
+        task::spawn_blocking(move || ops::wait_for_status(&job_path))
+            .await
+            .map_err(|err| OpsError::Unknown(err.to_string()))?
+    }
+
+    /// Same as `follow`, but yields the output as a stream of chunks. Ends once the task has
+    /// finished
+    pub fn follow_async(&self, id: &JobId) -> Result<FollowStream, OpsError> {
+        FollowStream::open(&self.job_path(id)?)
+    }
+}
+
+/// Stream of a queue file's contents as it is being written. See `Queue::follow_async`. Each
+/// chunk is read on tokio's blocking pool, where waiting for more output doesn't hold up the
+/// caller's thread
+pub struct FollowStream {
+    state: FollowState,
+}
+
+enum FollowState {
+    Idle(ops::Follow),
+    Reading(JoinHandle<(ops::Follow, io::Result<Vec<u8>>)>),
+    Finished,
+}
+
+impl FollowStream {
+    fn open(queue_path: &path::PathBuf) -> Result<Self, OpsError> {
+        Ok(Self {
+            state: FollowState::Idle(ops::Follow::open(queue_path)?),
+        })
+    }
+}
+
+impl Stream for FollowStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match mem::replace(&mut self.state, FollowState::Finished) {
+                FollowState::Idle(mut follow) => {
+                    self.state = FollowState::Reading(task::spawn_blocking(move || {
+                        let mut chunk = vec![0; CHUNK_LEN];
+                        let read = follow.read(&mut chunk).map(|len| {
+                            chunk.truncate(len);
+                            chunk
+                        });
+                        (follow, read)
+                    }));
+                }
+                FollowState::Reading(mut reading) => match Pin::new(&mut reading).poll(cx) {
+                    Poll::Pending => {
+                        self.state = FollowState::Reading(reading);
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(io::Error::other(err)))),
+                    Poll::Ready(Ok((_, Ok(chunk)))) if chunk.is_empty() => {
+                        return Poll::Ready(None)
+                    }
+                    Poll::Ready(Ok((follow, read))) => {
+                        self.state = FollowState::Idle(follow);
+                        return Poll::Ready(Some(read));
+                    }
+                },
+                FollowState::Finished => return Poll::Ready(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::{env, ffi, fs, io, path};
+
+use crate::ops::{CleanPolicy, IoPriority, QueueOptions, NICE_RANGE};
+use crate::parser::{parse_duration, parse_size};
+
+/// Settings of `$XDG_CONFIG_HOME/fnq/config.toml`, which defaults to `~/.config/fnq/config.toml`
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    /// Top level settings, used for every queue
+    pub defaults: QueueConfig,
+    /// `[queues.<name>]` tables, selected with `--queue <name>`
+    pub queues: HashMap<String, QueueConfig>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct QueueConfig {
+    pub dir: Option<path::PathBuf>,
+    pub quiet: Option<bool>,
+    pub clean: Option<CleanPolicy>,
+    pub timeout: Option<std::time::Duration>,
+    pub min_memory: Option<u64>,
+    pub max_load: Option<f64>,
+    pub nice: Option<i32>,
+    pub ionice: Option<IoPriority>,
+}
+
+fn config_path() -> Option<path::PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => path::PathBuf::from(config_home),
+        _ => path::PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("fnq").join("config.toml"))
+}
+
+/// Expands a leading `~`. Relative dirs would depend on where fnq happens to be run, which is what
+/// the config file is there to avoid, so they are refused
+fn parse_dir(dir: &str) -> Result<path::PathBuf, String> {
+    let dir = match dir.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = env::var_os("HOME").ok_or("dir starts with ~ but $HOME is not set")?;
+            path::PathBuf::from(format!("{}{}", home.to_string_lossy(), rest))
+        }
+        _ => path::PathBuf::from(dir),
+    };
+    if dir.is_relative() {
+        return Err(format!(
+            "dir must be an absolute path or start with ~/: {:?}",
+            dir
+        ));
+    }
+    Ok(dir)
+}
+
+impl QueueConfig {
+    fn parse(table: &toml::Table, allow_queues: bool) -> Result<Self, String> {
+        let mut config = Self::default();
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("dir", toml::Value::String(dir)) => config.dir = Some(parse_dir(dir)?),
+                ("quiet", toml::Value::Boolean(quiet)) => config.quiet = Some(*quiet),
+                // Same as --clean
+                ("clean", toml::Value::Boolean(true)) => config.clean = Some(CleanPolicy::Success),
+                ("clean", toml::Value::Boolean(false)) => config.clean = Some(CleanPolicy::Never),
+                ("clean", toml::Value::String(when)) => {
+                    config.clean = Some(
+                        CleanPolicy::parse(when)
+                            .ok_or("clean must be always, success, failure or never")?,
+                    )
+                }
+                ("timeout", toml::Value::String(duration)) => {
+                    config.timeout = Some(
+                        parse_duration(ffi::OsStr::new(duration))
+                            .ok_or("timeout must look like 45s, 30m, 12h or 7d")?,
+                    )
+                }
+                ("min_memory", toml::Value::String(size)) => {
+                    config.min_memory = Some(
+                        parse_size(ffi::OsStr::new(size))
+                            .ok_or("min_memory must look like 512M, 4G or a number of bytes")?,
+                    )
+                }
+                ("min_memory", toml::Value::Integer(bytes)) if *bytes >= 0 => {
+                    config.min_memory = Some(*bytes as u64)
+                }
+                ("max_load", toml::Value::Float(load)) if load.is_finite() && *load > 0.0 => {
+                    config.max_load = Some(*load)
+                }
+                ("max_load", toml::Value::Integer(load)) if *load > 0 => {
+                    config.max_load = Some(*load as f64)
+                }
+                ("nice", toml::Value::Integer(nice)) => {
+                    config.nice = Some(
+                        i32::try_from(*nice)
+                            .ok()
+                            .filter(|nice| NICE_RANGE.contains(nice))
+                            .ok_or("nice must be between -20 and 19")?,
+                    )
+                }
+                ("ionice", toml::Value::String(ionice)) => {
+                    config.ionice = Some(IoPriority::parse(ionice).ok_or(
+                        "ionice must be idle, best-effort[:<level>] or realtime[:<level>]",
+                    )?)
+                }
+                ("queues", _) if allow_queues => {}
+                _ => {
+                    return Err(format!(
+                        "unknown setting or wrong type: {} = {}",
+                        key, value
+                    ))
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Settings of `self`, with those it leaves out taken from `fallback`
+    fn or(&self, fallback: &QueueConfig) -> QueueConfig {
+        QueueConfig {
+            dir: self.dir.clone().or_else(|| fallback.dir.clone()),
+            quiet: self.quiet.or(fallback.quiet),
+            clean: self.clean.or(fallback.clean),
+            timeout: self.timeout.or(fallback.timeout),
+            min_memory: self.min_memory.or(fallback.min_memory),
+            max_load: self.max_load.or(fallback.max_load),
+            nice: self.nice.or(fallback.nice),
+            ionice: self.ionice.or(fallback.ionice),
+        }
+    }
+}
+
+impl Config {
+    /// A missing config file is the same as an empty one
+    pub fn load() -> Result<Self, String> {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|err| format!("{:?}: {}", path, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("Could not read {:?}: {}", path, err)),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let table: toml::Table = text
+            .parse()
+            .map_err(|err: toml::de::Error| err.to_string())?;
+        let mut queues = HashMap::new();
+        if let Some(queue_tables) = table.get("queues") {
+            let queue_tables = queue_tables
+                .as_table()
+                .ok_or("queues must be a table of [queues.<name>] tables")?;
+            for (name, queue_table) in queue_tables {
+                let queue_table = queue_table
+                    .as_table()
+                    .ok_or_else(|| format!("queues.{} must be a table", name))?;
+                let queue = QueueConfig::parse(queue_table, false)
+                    .map_err(|err| format!("queues.{}: {}", name, err))?;
+                queues.insert(name.clone(), queue);
+            }
+        }
+
+        Ok(Self {
+            defaults: QueueConfig::parse(&table, true)?,
+            queues,
+        })
+    }
+
+    /// Settings of the queue selected with `--queue`, if any
+    pub fn queue(&self, name: Option<&str>) -> Result<QueueConfig, String> {
+        match name {
+            None => Ok(QueueConfig::default()),
+            Some(name) => self
+                .queues
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("No queue named {:?} in the config file", name)),
+        }
+    }
+
+    /// The queue dir, by precedence: `--dir`, the `--queue`'s `dir`, `$FNQ_DIR`, the top level
+    /// `dir`, and else the current directory
+    pub fn queue_dir(&self, dir_arg: Option<ffi::OsString>, queue: &QueueConfig) -> ffi::OsString {
+        dir_arg
+            .or_else(|| queue.dir.clone().map(ffi::OsString::from))
+            .or_else(|| env::var_os("FNQ_DIR"))
+            .or_else(|| self.defaults.dir.clone().map(ffi::OsString::from))
+            .unwrap_or_else(|| ffi::OsString::from("."))
+    }
+
+    /// What the flags of a queued command start out as. Flags given on the command line win over
+    /// the `--queue`'s settings, which win over the top level ones
+    pub fn queue_options(&self, queue: &QueueConfig) -> QueueOptions {
+        let config = queue.or(&self.defaults);
+        QueueOptions {
+            quiet: config.quiet.unwrap_or_default(),
+            clean: config.clean.unwrap_or_default(),
+            timeout: config.timeout,
+            min_memory: config.min_memory,
+            max_load: config.max_load,
+            nice: config.nice,
+            ionice: config.ionice,
+            ..QueueOptions::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            r#"
+            dir = "/var/tmp/fnq"
+            quiet = true
+            clean = "failure"
+            max_load = 8
+
+            [queues.builds]
+            dir = "/tmp/builds"
+            clean = true
+            timeout = "30m"
+            min_memory = "4G"
+            ionice = "idle"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.defaults.dir, Some("/var/tmp/fnq".into()));
+        let builds = config.queue(Some("builds")).unwrap();
+        assert_eq!(
+            config.queue_options(&builds),
+            QueueOptions {
+                quiet: true,
+                clean: CleanPolicy::Success,
+                timeout: Some(time::Duration::from_secs(30 * 60)),
+                min_memory: Some(4 << 30),
+                max_load: Some(8.0),
+                ionice: IoPriority::parse("idle"),
+                ..QueueOptions::default()
+            }
+        );
+        assert_eq!(
+            config.queue_dir(None, &builds),
+            ffi::OsString::from("/tmp/builds")
+        );
+        assert_eq!(
+            config.queue_dir(Some("/elsewhere".into()), &builds),
+            ffi::OsString::from("/elsewhere")
+        );
+        assert!(config.queue(Some("tests")).is_err());
+
+        assert!(Config::parse("dir = \"relative/dir\"").is_err());
+        assert!(Config::parse("clean = \"sometimes\"").is_err());
+        assert!(Config::parse("quite = true").is_err());
+        assert!(Config::parse("max_load = 0").is_err());
+        assert!(Config::parse("nice = 40").is_err());
+        assert!(Config::parse("min_memory = \"lots\"").is_err());
+        assert!(Config::parse("[queues.builds]\nqueues = 1").is_err());
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+}
@@ -0,0 +1,40 @@
+//! A flock-based approach to queuing Unix tasks & processes
+//!
+//! Drives the same queue directories as the `fnq` binary, so tasks queued from Rust and from the
+//! command line wait on each other. Tasks are queued by running the `fnq` binary, which has to
+//! be in `$PATH` or at `$FNQ_BIN`.
+//!
+//! ```no_run
+//! use fnq::{Command, Queue};
+//!
+//! let queue = Queue::open("/tmp/fnq")?;
+//! let id = queue.enqueue(Command::new("make").arg("release"))?;
+//! println!("queued {}", id);
+//!
+//! match queue.wait(&id)? {
+//!     Some(status) if status.succeeded() => println!("done"),
+//!     status => println!("failed: {:?}", status),
+//! }
+//! # Ok::<(), fnq::OpsError>(())
+//! ```
+//!
+//! With the `async` feature, `Queue::wait_async` and `Queue::follow_async` wait on tokio's
+//! blocking thread pool instead of the runtime's threads.
+
+mod queue;
+
+#[cfg(feature = "async")]
+mod async_queue;
+
+// Shared with the fnq binary through the fnq-ops crate, which is pinned to the same version. The
+// types re-exported below are part of fnq's API and follow its semver, the rest of fnq-ops isn't
+pub(crate) use fnq_ops as ops;
+
+pub use ops::{
+    CgroupLimits, CleanPolicy, DuplicatePolicy, Event, EventKind, Events, Follow, IoClass,
+    IoPriority, Job, JobHeader, JobId, JobState, JobStatus, Limits, NamedLock, OpsError,
+};
+pub use queue::{Command, Queue};
+
+#[cfg(feature = "async")]
+pub use async_queue::FollowStream;
@@ -1,123 +1,640 @@
-use std::{env, ffi, fs, path, process};
+use std::{env, ffi, path, process, time};
 
-mod ops;
+use fnq_ops as ops;
+
+mod config;
 mod parser;
 
-static USAGE: &'static str = "fnq - A flock-based approach to queuing Unix tasks & processes
+static USAGE: &str = "fnq - A flock-based approach to queuing Unix tasks & processes
 
 USAGE:
-    fnq [FLAGS] <command>
+    fnq [--dir <dir>] [--queue <name>] <subcommand or flags>
+
+    fnq add [FLAGS] [--] <command> [args...]
+    fnq ls [--label <key>=<value>]...
+    fnq wait [<queue file> | --batch <batch>]
+    fnq tail [<queue file>]
+    fnq kill [--signal <signal>] <queue file>...
+    fnq rm <queue file>...
+    fnq help [<subcommand>]
+
+    fnq [FLAGS] [--] <command> [args...]
     fnq --tap <queue file>
     fnq --block <queue file>
+    fnq --tap --batch <batch>
+    fnq --block --batch <batch>
+    fnq --batch-start
+    fnq [FLAGS] --barrier
+    fnq [FLAGS] (--every <duration> | --cron <expression>) [--] <command> [args...]
+    fnq [FLAGS] (--on-change <path>)... [--] <command> [args...]
+    fnq --schedules
+    fnq --unschedule <schedule>...
     fnq --watch <queue file>
+    fnq --list [--label <key>=<value>]...
+    fnq --events
+    fnq --repair [--requeue]
+    fnq --resume
+    fnq --gc [--older-than <duration>] [--keep-last <n>] [--only-succeeded] [--archive]
+
+GLOBAL OPTIONS:
+        --dir <dir>   Directory to store queue files in. Wins over everything else
+        --queue <name>
+                      Uses the [queues.<name>] settings of the config file
+
+SUBCOMMANDS:
+    add               Queues command. Same as leaving out the subcommand, which
+                      only works for commands not named like a subcommand
+    ls                Same as --list
+    wait              Same as --block
+    tail              Prints queue file's contents as they are written until its
+                      process completes. Without a queue file, same as --watch
+    kill              Sends SIGTERM, or the --signal given, to the processes of
+                      running or waiting queue files
+    rm                Removes finished queue files
+    help              Prints help information for a subcommand
 
 FLAGS:
-    -c, --clean       Removes queue file after process complete
+    Short flags can be combined, as in -qcz, and -- ends the flags
+
+    -c, --clean       Removes queue file after process completes successfully
+        --clean=<when>
+                      Removes queue file after process completes, when is one of
+                      always, success, failure or never
+        --keep-last <n>
+                      After process completes, removes all but the last n
+                      finished queue files in FNQ_DIR
+        --archive     Moves removed queue files gzipped into FNQ_DIR/archive
+                      instead of deleting them
+    -z, --compress    Gzips queue file after process completes. --last reads
+                      compressed queue files transparently
+        --on-success <command>
+                      Runs command with sh -c once the process exits with
+                      status 0
+        --on-failure <command>
+                      Runs command with sh -c once the process did not succeed
+        --timeout <duration>
+                      Sends the process SIGTERM once it has run for duration,
+                      e.g. 90s or 2h, and SIGKILL 10s later if it still runs
+        --name <name> Name to refer to the queue file by. Wherever a queue file
+                      is expected, a name or a unique prefix of a queue file or
+                      name works too
+        --label <key>=<value>
+                      Label that --list can filter by. Can be given more than once
+        --batch <batch>
+                      Adds the process to a group that --tap --batch and
+                      --block --batch check on together
+        --every <duration>
+                      Instead of queueing command once, starts a background
+                      scheduler that queues it right away and then every
+                      duration. Prints the schedule's id. A run is skipped while
+                      the one queued before it is still waiting or running
+        --cron <expression>
+                      Same as --every, but queues command whenever the five field
+                      cron expression (minute hour day month weekday, in local
+                      time) matches, e.g. \"*/15 * * * *\" or @daily
+        --schedules   Prints every schedule with whether its scheduler is still
+                      active, its --every or --cron, command and the queue file it
+                      queued last. Schedulers do not survive a reboot
+        --unschedule  Removes schedules, stopping their schedulers
+        --on-change <path>
+                      Instead of queueing command once, watches path (and what
+                      is below it) until interrupted, and queues command once
+                      changes have settled for half a second. Can be given more
+                      than once. Changes are ignored while the process queued
+                      before is still waiting
+        --unique      Does not queue command if the same command with the same
+                      arguments, queued from the same directory, is still waiting
+                      for its turn, and prints that queue file instead
+        --replace     Same as --unique, but kills and removes the waiting queue
+                      file and queues command anew
+        --lock <name>[:<n>]
+                      Once its turn has come, the process also waits for the
+                      named lock and holds it until it exits, so processes with
+                      the same lock never overlap, whichever FNQ_DIR they are
+                      in. With :n, up to n of them run at once. Can be given
+                      more than once
+        --min-memory <size>
+                      Once its turn has come, the process waits until at least
+                      size of memory is available (MemAvailable in
+                      /proc/meminfo), e.g. 512M or 4G
+        --max-load <load>
+                      Once its turn has come, the process waits until the 1
+                      minute load average in /proc/loadavg is below load
+        --limit-mem <size>
+                      Limits the process's address space with setrlimit, e.g.
+                      2G. Allocations beyond it fail
+        --limit-cpu <duration>
+                      Limits the process's CPU time, e.g. 90s. Going over it
+                      kills the process with SIGXCPU, which its status line
+                      says
+        --limit-nofile <n>
+                      Limits the number of files the process can have open
+        --limit-core <size>
+                      Limits the size of core dumps. 0 turns them off
+        --cgroup      Runs the process in a cgroup of its own, so that what it
+                      leaves behind is killed with it and its memory peak is
+                      recorded
+        --memory-max <size>
+                      Limits the memory of the process and everything it spawns
+                      with the memory.max of its cgroup. Going over it gets
+                      them killed, which its status line says
+        --cpu-max <cpus>
+                      Limits the process and everything it spawns to this many
+                      CPUs with the cpu.max of its cgroup, e.g. 1.5
+        --nice <n>    Runs the process with nice value n, from -20 to 19
+        --ionice <class>[:<level>]
+                      Runs the process in IO scheduling class idle, best-effort
+                      or realtime, the latter two with a level from 0 (highest)
+                      to 7 (lowest)
     -q, --quiet       No print out of queue file to stdout
+        --no-quiet    Prints queue file to stdout even if the config sets quiet
     -t, --tap         Determines if queue file's process is complete. If no
-                      queue file specified, then checks all in FNQ_DIR
-    -b, --block       Will block if queue file's process is not complete. If no
-                      queue file specified, then blocks on all in FNQ_DIR
+                      queue file specified, then checks all in FNQ_DIR. With
+                      --batch, checks all queued with --batch <batch>
+    -b, --block, --wait
+                      Will block if queue file's process is not complete. If no
+                      queue file specified, then blocks on all in FNQ_DIR. With
+                      --batch, blocks on all queued with --batch <batch>
+        --batch-start Prints a new unique batch name to use with --batch
+        --barrier     Queues a marker instead of a command. Everything queued after
+                      it waits until all queued before it has completed. It exits
+                      with status 0 if all since the previous marker succeeded,
+                      and else lists those that didn't in its queue file
     -w, --watch       Similar to --block but will print to stdout contents of the
                       currently running queue files
     -l, --last        Prints out last queue file
+        --list [--label <key>=<value>]...
+                      Prints every queue file with its state, command, name and
+                      labels. With --label, only those that have the label
+        --events      Prints a line for every job that is enqueued, started, writes
+                      output, exits or is removed from now on, as
+                      <unix time>\t<queue file>\t<event>[\t<status>]
+        --repair      Marks queue files whose supervising fnq process died
+                      (e.g. OOM or reboot) with [supervisor lost.]. With
+                      --requeue, also queues their commands again
+        --resume      Same as --repair --requeue. Queues every task that never
+                      finished (e.g. dropped by a reboot) again in order, with
+                      its original arguments, cwd, env and flags
+        --gc          Removes finished queue files. Running and waiting queue files
+                      are never removed. Durations look like 45s, 30m, 12h or 7d
+
     -v, --version     Prints version information
     -h, --help        Prints help information
+
+ENV:
+    FNQ_DIR           Directory to store queue files in, if there is no --dir or
+                      --queue with a dir. Defaults to the config file's dir, and
+                      else to $(pwd)
+    FNQ_GC            Options for --gc to run on every enqueue, e.g.
+                      FNQ_GC=\"--keep-last 100 --only-succeeded\"
+    FNQ_LOCK_DIR      Directory for the --lock files. Defaults to
+                      $XDG_RUNTIME_DIR/fnq/locks, and else to fnq-<uid>/locks in
+                      the temp dir
+    FNQ_CGROUP        Cgroup v2 directory that processes queued with --cgroup,
+                      --memory-max or --cpu-max get a cgroup of their own in.
+                      Defaults to the cgroup fnq runs in
+
+CONFIG:
+    $XDG_CONFIG_HOME/fnq/config.toml, by default ~/.config/fnq/config.toml, can
+    set dir, quiet, clean, timeout, min_memory, max_load, nice and ionice at
+    the top level and in [queues.<name>] tables. Flags on the command line win
+    over the --queue's settings, which win over the top level ones. For the
+    directory, the order is --dir, the --queue's dir, FNQ_DIR, the top level
+    dir and then $(pwd). --timeout, --min-memory, --max-load, --nice and --ionice
+    take none to unset the configured value
+
+HOOKS:
+    Executables named on-start, on-success or on-failure in FNQ_DIR/hooks run
+    when a process starts, exits with status 0 or does not succeed. They and
+    the --on-success/--on-failure commands get FNQJOBID, FNQ_QUEUE_FILE,
+    FNQ_HOOK, FNQ_DIR and, once the process exited, FNQ_EXIT_CODE and
+    FNQ_DURATION (in seconds) in their environment
+
+EXIT CODES:
+    0                 Success (--tap: queue file's process is not running)
+    1                 --tap: queue file's process is still running
+    2                 Usage error, or invalid config file
+    3                 Queue file or schedule not found, queue file ambiguous,
+                      or queue is empty
+    4                 IO error
+    5                 Could not lock a queue file
+    6                 --block on a queue file whose process did not succeed, or
+                      on a batch with a process that did not succeed
+    7                 kill on a finished queue file, or rm on a running one
+    70                Any other error
+    126               Command is not executable
+    127               Command not found
 ";
 
-static VERSION: &'static str = env!("CARGO_PKG_VERSION");
+static ADD_USAGE: &str = "Queues command to run once every queue file before it has completed
+
+USAGE:
+    fnq add [FLAGS] [--] <command> [args...]
+
+FLAGS:
+    -q, --quiet, --no-quiet, -c, --clean, --clean=<when>, --keep-last <n>,
+    --archive, -z, --compress, --on-success <command>, --on-failure <command>,
+    --timeout <duration>, --name <name>, --label <key>=<value>,
+    --batch <batch>, --unique, --replace, --lock <name>[:<n>],
+    --min-memory <size>, --max-load <load>, --limit-mem <size>,
+    --limit-cpu <duration>, --limit-nofile <n>, --limit-core <size>,
+    --cgroup, --memory-max <size>, --cpu-max <cpus>, --nice <n>,
+    --ionice <class>[:<level>], --every <duration>, --cron <expression>,
+    --on-change <path>
+
+    See fnq --help. Short flags can be combined, as in -qcz, and -- ends
+    the flags
+";
+
+static LS_USAGE: &str = "Prints every queue file with its state, command, name and labels
+
+USAGE:
+    fnq ls [--label <key>=<value>]...
+
+FLAGS:
+        --label <key>=<value>
+                      Only prints queue files with this label. Can be given more
+                      than once
+";
+
+static WAIT_USAGE: &str = "Blocks until queue file's process has completed. If no queue file specified,
+then blocks on all in FNQ_DIR. Exits with 6 if the process did not succeed
+
+USAGE:
+    fnq wait [<queue file>]
+    fnq wait --batch <batch>
+
+FLAGS:
+        --batch <batch>
+                      Blocks on every queue file queued with --batch <batch>.
+                      Exits with 6 if any of their processes did not succeed
+";
+
+static TAIL_USAGE: &str = "Prints queue file's contents as they are written until its process has
+completed. If no queue file specified, then prints the currently running ones
+
+USAGE:
+    fnq tail [<queue file>]
+";
+
+static KILL_USAGE: &str = "Sends a signal to the processes of running or waiting queue files. Processes
+that started get it sent to their whole process group
+
+USAGE:
+    fnq kill [--signal <signal>] <queue file>...
+
+FLAGS:
+    -s, --signal <signal>
+                      Signal to send, as in TERM, SIGTERM or 15. Defaults to TERM
+";
+
+static RM_USAGE: &str = "Removes finished queue files. Fails on running or waiting ones
+
+USAGE:
+    fnq rm <queue file>...
+
+Only queue files in the queue dir are removed. To queue rm instead, use
+fnq add rm <args> or fnq -- rm <args>
+";
+
+static VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn print_usage() {
     println!("{}", USAGE);
 }
 
-fn ensure_dir(dir: ffi::OsString) -> path::PathBuf {
-    let dir_path: path::PathBuf = dir.into();
-    if !dir_path.exists() {
-        // TODO: change to correct permissions? (0777)
-        fs::create_dir(&dir_path)
-            .expect(format!("Was unable to create dir {:?}", dir_path).as_str());
-    } else if !dir_path.is_dir() {
-        panic!("$FNQ_DIR is not a directory");
+fn subcommand_usage(subcommand: parser::Subcommand) -> &'static str {
+    use parser::Subcommand;
+
+    match subcommand {
+        Subcommand::Add => ADD_USAGE,
+        Subcommand::Ls => LS_USAGE,
+        Subcommand::Wait => WAIT_USAGE,
+        Subcommand::Tail => TAIL_USAGE,
+        Subcommand::Kill => KILL_USAGE,
+        Subcommand::Rm => RM_USAGE,
     }
-    dir_path
 }
 
+/// Exit code for each kind of error, so scripts can tell failure modes apart. See EXIT CODES
+fn exit_code(err: &ops::OpsError) -> i32 {
+    use ops::OpsError;
+
+    match err {
+        OpsError::FileNotFound(_)
+        | OpsError::AmbiguousJob(_)
+        | OpsError::ScheduleNotFound(_)
+        | OpsError::QueueEmpty => 3,
+        OpsError::IO(_) | OpsError::PathIO(..) => 4,
+        OpsError::Lock(..) => 5,
+        OpsError::JobFailed(..) => 6,
+        OpsError::JobNotRunning(_) | OpsError::JobRunning(_) => 7,
+        OpsError::NotExecutable(_) => 126,
+        OpsError::CommandNotFound(_) => 127,
+        _ => 70,
+    }
+}
+
+fn exit_with_error(err: ops::OpsError) -> ! {
+    eprintln!("Error: {}", err);
+    process::exit(exit_code(&err));
+}
+
+/// Unique enough not to clash with the batch of another script sharing the queue dir
+fn new_batch_id() -> String {
+    let millis = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("batch{}.{}", millis, process::id())
+}
+
+fn exit_with_config_error(err: String) -> ! {
+    eprintln!("Error: {}", err);
+    process::exit(2);
+}
+
+/// Queue files can be referred to by their file name, the job's name, or a unique prefix of either
 fn get_queue_path(
     dir_path: &path::PathBuf,
     queue_file: Option<ffi::OsString>,
-) -> Result<Option<path::PathBuf>, String> {
-    if let Some(file) = queue_file {
-        let mut queue_path = path::PathBuf::from(&dir_path);
-        queue_path.push(&file);
-        if !queue_path.exists() {
-            return Err(format!("{:?} does not exist", queue_path));
-        }
-        return Ok(Some(queue_path));
-    }
-    Ok(None)
+) -> Result<Option<path::PathBuf>, ops::OpsError> {
+    queue_file
+        .map(|file| ops::find(dir_path, &file))
+        .transpose()
+}
+
+fn get_queue_paths(
+    dir_path: &path::PathBuf,
+    queue_files: Vec<ffi::OsString>,
+) -> Result<Vec<path::PathBuf>, ops::OpsError> {
+    queue_files
+        .into_iter()
+        .filter_map(|file| get_queue_path(dir_path, Some(file)).transpose())
+        .collect()
 }
 
 fn main() {
     use parser::ParseResult;
 
-    let args = env::args_os().collect();
-    let fnq_dir = env::var_os("FNQ_DIR").unwrap_or(ffi::OsString::from("."));
-    let dir_path = ensure_dir(fnq_dir);
-    match parser::parse_args(args) {
+    let mut args = env::args_os().collect();
+    let global_options = parser::parse_global_options(&mut args).unwrap_or_else(|| {
+        print_usage();
+        process::exit(2);
+    });
+    // Help and version need neither the config nor the queue dir, so a broken config file can't
+    // keep them from printing, and nothing is created for them
+    match parser::parse_args(args.clone(), &ops::QueueOptions::default()) {
         ParseResult::Version => {
             println!("{}", VERSION);
+            return;
         }
         ParseResult::Help => {
             print_usage();
+            return;
+        }
+        ParseResult::CommandHelp(subcommand) => {
+            println!("{}", subcommand_usage(subcommand));
+            return;
+        }
+        _ => {}
+    }
+    let config = config::Config::load().unwrap_or_else(|err| exit_with_config_error(err));
+    let queue_config = config
+        .queue(global_options.queue.as_deref())
+        .unwrap_or_else(|err| exit_with_config_error(err));
+    let fnq_dir = config.queue_dir(global_options.dir, &queue_config);
+    let dir_path = fnq::Queue::open(fnq_dir)
+        .unwrap_or_else(|err| exit_with_error(err))
+        .dir()
+        .to_path_buf();
+    match parser::parse_args(args, &config.queue_options(&queue_config)) {
+        ParseResult::Version | ParseResult::Help | ParseResult::CommandHelp(_) => {
+            unreachable!("handled before the config is loaded")
         }
         ParseResult::Error => {
             print_usage();
-            process::exit(1);
-        }
-        ParseResult::Tap(queue_file) => match get_queue_path(&dir_path, queue_file) {
-            Err(err) => {
-                eprintln!("Error: {}", err);
-            }
-            Ok(queue_path) => {
-                if let Err(err) = ops::tap(&dir_path, queue_path).map(|is_running| {
-                    if is_running {
-                        println!("running!");
-                        process::exit(1);
-                    } else {
-                        println!("not running!");
-                        process::exit(0);
+            process::exit(2);
+        }
+        ParseResult::CommandError(subcommand) => {
+            eprintln!("{}", subcommand_usage(subcommand));
+            process::exit(2);
+        }
+        ParseResult::Tap(queue_file) => {
+            let is_running = get_queue_path(&dir_path, queue_file)
+                .and_then(|queue_path| ops::tap(&dir_path, queue_path))
+                .unwrap_or_else(|err| exit_with_error(err));
+            if is_running {
+                println!("running!");
+                process::exit(1);
+            } else {
+                println!("not running!");
+                process::exit(0);
+            }
+        }
+        ParseResult::TapBatch(batch) => {
+            let is_running =
+                ops::tap_batch(&dir_path, &batch).unwrap_or_else(|err| exit_with_error(err));
+            if is_running {
+                println!("running!");
+                process::exit(1);
+            } else {
+                println!("not running!");
+                process::exit(0);
+            }
+        }
+        ParseResult::BlockBatch(batch) => {
+            if let Err(err) = ops::block_batch(dir_path, &batch) {
+                exit_with_error(err);
+            }
+        }
+        ParseResult::BatchStart => {
+            println!("{}", new_batch_id());
+        }
+        ParseResult::Block(queue_file) => {
+            if let Err(err) = get_queue_path(&dir_path, queue_file)
+                .and_then(|queue_path| ops::block(dir_path, queue_path))
+            {
+                exit_with_error(err);
+            }
+        }
+        ParseResult::Queue(fnd_cmd, task_cmd, task_args, options) => {
+            if let Some(gc_args) = env::var_os("FNQ_GC") {
+                let gc_args: Vec<ffi::OsString> = gc_args
+                    .to_string_lossy()
+                    .split_whitespace()
+                    .map(ffi::OsString::from)
+                    .collect();
+                match parser::parse_gc_policy(&gc_args) {
+                    None => eprintln!("Error: $FNQ_GC is not a valid --gc policy"),
+                    Some(policy) => {
+                        if let Err(err) = ops::gc(&dir_path, &policy) {
+                            eprintln!("Error: {}", err)
+                        }
                     }
-                }) {
-                    eprintln!("Error {:?}", err);
                 }
             }
-        },
-        ParseResult::Block(queue_file) => match get_queue_path(&dir_path, queue_file) {
-            Err(err) => {
-                eprintln!("{}", err);
+            let quiet = options.quiet;
+            match ops::queue(fnd_cmd, task_cmd, task_args, dir_path, options) {
+                Err(err) => exit_with_error(err),
+                Ok(task_filename) if !quiet => println!("{}", task_filename.to_string_lossy()),
+                Ok(_) => {}
+            }
+        }
+        ParseResult::Barrier(fnd_cmd, options) => {
+            let quiet = options.quiet;
+            match ops::queue_barrier(fnd_cmd, dir_path, options) {
+                Err(err) => exit_with_error(err),
+                Ok(task_filename) if !quiet => println!("{}", task_filename.to_string_lossy()),
+                Ok(_) => {}
+            }
+        }
+        ParseResult::Schedule(fnd_cmd, task_cmd, task_args, options, schedule) => {
+            let quiet = options.quiet;
+            match ops::schedule(fnd_cmd, task_cmd, task_args, dir_path, options, schedule) {
+                Err(err) => exit_with_error(err),
+                Ok(id) if !quiet => println!("{}", id.to_string_lossy()),
+                Ok(_) => {}
             }
-            Ok(queue_path) => {
-                if let Err(err) = ops::block(dir_path, queue_path) {
-                    eprintln!("Error {:?}", err);
+        }
+        ParseResult::OnChange(fnd_cmd, task_cmd, task_args, options, paths) => {
+            if let Err(err) = ops::on_change(fnd_cmd, task_cmd, task_args, dir_path, options, &paths)
+            {
+                exit_with_error(err);
+            }
+        }
+        ParseResult::Schedules => match ops::schedules(&dir_path) {
+            Err(err) => exit_with_error(err),
+            Ok(schedules) => {
+                for schedule in schedules {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        schedule.id.to_string_lossy(),
+                        if schedule.active { "active" } else { "stopped" },
+                        schedule.schedule.to_string_lossy(),
+                        schedule.command.to_string_lossy(),
+                        schedule.last_queued.unwrap_or_default().to_string_lossy()
+                    );
                 }
             }
         },
-        ParseResult::Queue(fnd_cmd, task_cmd, task_args, quiet, clean) => {
-            if let Err(err) = ops::queue(fnd_cmd, task_cmd, task_args, dir_path, quiet, clean) {
-                // Note: possibly could be another process in which this writes to a different stdout
-                eprintln!("Error: {:?}", err)
+        ParseResult::Unschedule(ids) => {
+            if let Err(err) = ops::unschedule(&dir_path, &ids) {
+                exit_with_error(err);
             }
         }
         ParseResult::Watch => {
             if let Err(err) = ops::watch(dir_path) {
-                eprintln!("Error: {:?}", err)
+                exit_with_error(err);
+            }
+        }
+        ParseResult::Tail(queue_file) => {
+            let result = get_queue_path(&dir_path, queue_file).and_then(|queue_path| {
+                match queue_path {
+                    Some(queue_path) => ops::tail(&queue_path),
+                    None => ops::watch(dir_path),
+                }
+            });
+            if let Err(err) = result {
+                exit_with_error(err);
+            }
+        }
+        ParseResult::Kill(signal, queue_files) => {
+            if let Err(err) = get_queue_paths(&dir_path, queue_files)
+                .and_then(|queue_paths| ops::kill(&queue_paths, signal))
+            {
+                exit_with_error(err);
+            }
+        }
+        ParseResult::Remove(queue_files) => {
+            if let Err(err) = get_queue_paths(&dir_path, queue_files)
+                .and_then(|queue_paths| ops::rm(&dir_path, &queue_paths))
+            {
+                exit_with_error(err);
             }
         }
         ParseResult::Last => {
-            if let Err(err)  = ops::last(dir_path) {
-                eprintln!("Error: {:?}", err)
+            if let Err(err) = ops::last(dir_path) {
+                exit_with_error(err);
+            }
+        }
+        ParseResult::List(labels) => match ops::list(&dir_path) {
+            Err(err) => exit_with_error(err),
+            Ok(jobs) => {
+                for job in jobs.into_iter().filter(|job| job.has_labels(&labels)) {
+                    let labels: Vec<String> = job
+                        .labels
+                        .iter()
+                        .map(|(key, value)| {
+                            format!("{}={}", key.to_string_lossy(), value.to_string_lossy())
+                        })
+                        .collect();
+                    let mut priority = Vec::new();
+                    if let Some(nice) = job.nice {
+                        priority.push(format!("nice={}", nice));
+                    }
+                    if let Some(ionice) = job.ionice {
+                        priority.push(format!("ionice={}", ionice));
+                    }
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}\t{}",
+                        job.filepath.file_name().unwrap_or_default().to_string_lossy(),
+                        job.state,
+                        job.header.command().unwrap_or_default().to_string_lossy(),
+                        job.name.unwrap_or_default().to_string_lossy(),
+                        labels.join(" "),
+                        priority.join(" ")
+                    );
+                }
+            }
+        },
+        ParseResult::Events => {
+            let events = ops::Events::new(&dir_path).unwrap_or_else(|err| exit_with_error(err));
+            for event in events {
+                let event = event.unwrap_or_else(|err| exit_with_error(err));
+                let time = event
+                    .time
+                    .duration_since(time::UNIX_EPOCH)
+                    .unwrap_or_default();
+                println!(
+                    "{}.{:03}\t{}\t{}",
+                    time.as_secs(),
+                    time.subsec_millis(),
+                    event.id.to_string_lossy(),
+                    event.kind
+                );
             }
         }
+        ParseResult::Repair(requeue) => match ops::repair(&dir_path, requeue) {
+            Err(err) => exit_with_error(err),
+            Ok(repaired) => {
+                let mut requeue_failed = false;
+                for job in repaired {
+                    match job.requeued {
+                        None => println!("{}", job.filepath.to_string_lossy()),
+                        Some(requeued) => println!(
+                            "{} -> {}",
+                            job.filepath.to_string_lossy(),
+                            requeued.to_string_lossy()
+                        ),
+                    }
+                    if let Some(err) = job.requeue_error {
+                        eprintln!("Error: {}", err);
+                        requeue_failed = true;
+                    }
+                }
+                if requeue_failed {
+                    process::exit(1);
+                }
+            }
+        },
+        ParseResult::Gc(policy) => match ops::gc(&dir_path, &policy) {
+            Err(err) => exit_with_error(err),
+            Ok(removed) => {
+                for path in removed {
+                    println!("{}", path.to_string_lossy());
+                }
+            }
+        },
     }
 }
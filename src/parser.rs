@@ -1,25 +1,209 @@
-use std::ffi;
+use std::convert::TryFrom;
+use std::os::unix::prelude::*;
+use std::{ffi, path, time};
+
+use nix::sys::signal;
+
+use crate::ops::{
+    CleanPolicy, Cron, DuplicatePolicy, GcPolicy, IoPriority, NamedLock, QueueOptions, Schedule,
+    NICE_RANGE,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum ParseResult {
     Error,
     Tap(Option<ffi::OsString>),
     Block(Option<ffi::OsString>),
-    Queue(ffi::OsString, ffi::OsString, Vec<ffi::OsString>, bool, bool),
+    /// `--tap --batch <batch>`
+    TapBatch(ffi::OsString),
+    /// `--block --batch <batch>`
+    BlockBatch(ffi::OsString),
+    BatchStart,
+    /// `fnq [FLAGS] --barrier`
+    Barrier(ffi::OsString, QueueOptions),
+    Queue(ffi::OsString, ffi::OsString, Vec<ffi::OsString>, QueueOptions),
+    /// `--every` or `--cron` in front of the task command
+    Schedule(
+        ffi::OsString,
+        ffi::OsString,
+        Vec<ffi::OsString>,
+        QueueOptions,
+        Schedule,
+    ),
+    Schedules,
+    Unschedule(Vec<ffi::OsString>),
+    /// `--on-change <path>` in front of the task command, once for every watched path
+    OnChange(
+        ffi::OsString,
+        ffi::OsString,
+        Vec<ffi::OsString>,
+        QueueOptions,
+        Vec<path::PathBuf>,
+    ),
     Watch,
     Last,
+    Gc(GcPolicy),
+    Repair(bool),
+    /// Only jobs with all of these labels
+    List(Vec<(ffi::OsString, ffi::OsString)>),
+    Events,
+    Tail(Option<ffi::OsString>),
+    Kill(signal::Signal, Vec<ffi::OsString>),
+    Remove(Vec<ffi::OsString>),
     Help,
+    CommandHelp(Subcommand),
+    CommandError(Subcommand),
     Version,
 }
 
-pub fn parse_args(mut args: Vec<ffi::OsString>) -> ParseResult {
+/// `fnq add`, `fnq ls` and so on. The flags from before subcommands existed are kept as aliases
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subcommand {
+    Add,
+    Ls,
+    Wait,
+    Tail,
+    Kill,
+    Rm,
+}
+
+impl Subcommand {
+    fn parse(arg: &ffi::OsStr) -> Option<Self> {
+        match arg.to_str()? {
+            "add" => Some(Subcommand::Add),
+            "ls" => Some(Subcommand::Ls),
+            "wait" => Some(Subcommand::Wait),
+            "tail" => Some(Subcommand::Tail),
+            "kill" => Some(Subcommand::Kill),
+            "rm" => Some(Subcommand::Rm),
+            _ => None,
+        }
+    }
+}
+
+/// Options that go in front of the subcommand or flags, as in `fnq --dir /tmp/q ls`
+#[derive(Debug, Default, PartialEq)]
+pub struct GlobalOptions {
+    pub dir: Option<ffi::OsString>,
+    /// Name of a queue defined in the config file
+    pub queue: Option<String>,
+}
+
+/// Takes the global options off the front of `args`
+pub fn parse_global_options(args: &mut Vec<ffi::OsString>) -> Option<GlobalOptions> {
+    let mut options = GlobalOptions::default();
+    let mut parser = OptParser::new(args.split_off(args.len().min(1)));
+
+    while let Some(opt) = parser.peek_opt() {
+        if opt.name != "--dir" && opt.name != "--queue" {
+            break;
+        }
+        let opt = parser.next_opt()?;
+        let value = opt.value.or_else(|| parser.value())?;
+        if opt.name == "--dir" {
+            options.dir = Some(value);
+        } else {
+            options.queue = Some(value.into_string().ok()?);
+        }
+    }
+
+    args.extend(parser.operands());
+    Some(options)
+}
+
+/// Parses durations such as `90`, `45s`, `30m`, `12h` or `7d`. No unit means seconds
+pub fn parse_duration(arg: &ffi::OsStr) -> Option<time::Duration> {
+    let arg = arg.to_str()?;
+    let (amount, unit_secs) = match arg.char_indices().last()? {
+        (i, 's') => (&arg[..i], 1),
+        (i, 'm') => (&arg[..i], 60),
+        (i, 'h') => (&arg[..i], 60 * 60),
+        (i, 'd') => (&arg[..i], 60 * 60 * 24),
+        (i, 'w') => (&arg[..i], 60 * 60 * 24 * 7),
+        _ => (arg, 1),
+    };
+    let amount: u64 = amount.parse().ok()?;
+    Some(time::Duration::from_secs(amount.checked_mul(unit_secs)?))
+}
+
+/// Parses sizes in bytes such as `4096`, `512K`, `256M`, `4G` or `1T`. Units are powers of 1024
+pub fn parse_size(arg: &ffi::OsStr) -> Option<u64> {
+    let arg = arg.to_str()?;
+    let (amount, unit_bytes) = match arg.char_indices().last()? {
+        (i, 'K') | (i, 'k') => (&arg[..i], 1 << 10),
+        (i, 'M') | (i, 'm') => (&arg[..i], 1 << 20),
+        (i, 'G') | (i, 'g') => (&arg[..i], 1 << 30),
+        (i, 'T') | (i, 't') => (&arg[..i], 1 << 40),
+        _ => (arg, 1),
+    };
+    let amount: u64 = amount.parse().ok()?;
+    amount.checked_mul(unit_bytes)
+}
+
+/// Parses a positive number, such as a load average to stay below or a number of CPUs
+fn parse_positive(arg: &ffi::OsStr) -> Option<f64> {
+    let number: f64 = arg.to_str()?.parse().ok()?;
+    Some(number).filter(|number| number.is_finite() && *number > 0.0)
+}
+
+/// Parses a nice value between -20 and 19
+pub fn parse_nice(arg: &ffi::OsStr) -> Option<i32> {
+    let nice = arg.to_str()?.parse().ok()?;
+    Some(nice).filter(|nice| NICE_RANGE.contains(nice))
+}
+
+/// `none` unsets a flag the config file gave a default for, anything else is up to `parse`
+fn parse_or_none<T>(
+    value: ffi::OsString,
+    parse: impl FnOnce(&ffi::OsStr) -> Option<T>,
+) -> Option<Option<T>> {
+    if value == "none" {
+        return Some(None);
+    }
+    parse(&value).map(Some)
+}
+
+/// Parses the options following `--gc`. Also used for the policy in `$FNQ_GC`
+pub fn parse_gc_policy(args: &[ffi::OsString]) -> Option<GcPolicy> {
+    let mut policy = GcPolicy::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--older-than" {
+            policy.older_than = Some(parse_duration(args.next()?)?);
+        } else if arg == "--keep-last" {
+            policy.keep_last = Some(args.next()?.to_str()?.parse().ok()?);
+        } else if arg == "--only-succeeded" {
+            policy.only_succeeded = true;
+        } else if arg == "--archive" {
+            policy.archive = true;
+        } else {
+            return None;
+        }
+    }
+
+    Some(policy)
+}
+
+/// Parses everything after the global options. `defaults` are the queue options that flags of a
+/// queued command start out from, as set in the config file
+pub fn parse_args(mut args: Vec<ffi::OsString>, defaults: &QueueOptions) -> ParseResult {
     let len = args.len();
     if len < 2 {
         return ParseResult::Error;
     }
 
     let arg = &args[1];
-    if arg == "--help" || arg == "-h" {
+    if let Some(subcommand) = Subcommand::parse(arg) {
+        let fnq_cmd = args.drain(0..2).next().unwrap();
+        return parse_subcommand(fnq_cmd, subcommand, args, defaults);
+    } else if arg == "help" {
+        return match args.get(2).map(|arg| Subcommand::parse(arg)) {
+            None => ParseResult::Help,
+            Some(Some(subcommand)) => ParseResult::CommandHelp(subcommand),
+            Some(None) => ParseResult::Error,
+        };
+    } else if arg == "--help" || arg == "-h" {
         return ParseResult::Help;
     } else if arg == "--version" || arg == "-v" {
         return ParseResult::Version;
@@ -27,17 +211,68 @@ pub fn parse_args(mut args: Vec<ffi::OsString>) -> ParseResult {
         return ParseResult::Watch;
     } else if arg == "--last" || arg == "-l" {
         return ParseResult::Last;
+    } else if arg == "--list" {
+        let mut parser = OptParser::new(args.split_off(2));
+        let mut labels = Vec::new();
+        while let Some(opt) = parser.next_opt() {
+            match parse_label_filter(&opt, &mut parser) {
+                Some(label) => labels.push(label),
+                None => return ParseResult::Error,
+            }
+        }
+        return if parser.operands().is_empty() {
+            ParseResult::List(labels)
+        } else {
+            ParseResult::Error
+        };
+    } else if arg == "--schedules" {
+        return if len == 2 {
+            ParseResult::Schedules
+        } else {
+            ParseResult::Error
+        };
+    } else if arg == "--unschedule" {
+        return if len > 2 {
+            ParseResult::Unschedule(args.split_off(2))
+        } else {
+            ParseResult::Error
+        };
+    } else if arg == "--events" {
+        return ParseResult::Events;
+    } else if arg == "--resume" {
+        return ParseResult::Repair(true);
+    } else if arg == "--repair" {
+        return match &args[2..] {
+            [] => ParseResult::Repair(false),
+            [requeue] if requeue == "--requeue" => ParseResult::Repair(true),
+            _ => ParseResult::Error,
+        };
+    } else if arg == "--gc" {
+        return match parse_gc_policy(&args[2..]) {
+            Some(policy) => ParseResult::Gc(policy),
+            None => ParseResult::Error,
+        };
+    } else if arg == "--batch-start" {
+        return if len == 2 {
+            ParseResult::BatchStart
+        } else {
+            ParseResult::Error
+        };
     } else if arg == "--tap" || arg == "-t" {
         return if len == 2 {
             ParseResult::Tap(None)
+        } else if let Some(batch) = parse_batch(&args[2..]) {
+            ParseResult::TapBatch(batch)
         } else if len == 3 {
             ParseResult::Tap(args.drain(2..3).next())
         } else {
             ParseResult::Error
         };
-    } else if arg == "--block" || arg == "-b" {
+    } else if arg == "--block" || arg == "-b" || arg == "--wait" {
         return if len == 2 {
             ParseResult::Block(None)
+        } else if let Some(batch) = parse_batch(&args[2..]) {
+            ParseResult::BlockBatch(batch)
         } else if len == 3 {
             ParseResult::Block(args.drain(2..3).next())
         } else {
@@ -45,30 +280,355 @@ pub fn parse_args(mut args: Vec<ffi::OsString>) -> ParseResult {
         };
     }
 
-    let mut index: usize = 1;
-    let mut quiet = false;
-    let mut clean = false;
+    let fnq_cmd = args.drain(0..1).next().unwrap();
+    let mut parser = OptParser::new(args);
+    let mut options = defaults.clone();
+    let mut trigger = None;
+    loop {
+        options = match parse_queue_options(&mut parser, &options) {
+            Some(options) => options,
+            None => return ParseResult::Error,
+        };
+        // An option that isn't a queue option, or else the `--`
+        match parser.next_opt() {
+            None => break,
+            // Takes the queue flags that concern the marker itself, e.g. --name or --clean
+            Some(opt) if opt.name == "--barrier" && opt.value.is_none() && trigger.is_none() => {
+                return if parser.next_opt().is_none() && parser.operands().is_empty() {
+                    ParseResult::Barrier(fnq_cmd, options)
+                } else {
+                    ParseResult::Error
+                };
+            }
+            Some(opt) => {
+                if parse_trigger(&opt, &mut parser, &mut trigger).is_none() {
+                    return ParseResult::Error;
+                }
+            }
+        }
+    }
+    queue(fnq_cmd, parser.operands(), options, trigger).unwrap_or(ParseResult::Error)
+}
+
+/// `fnq <subcommand> ...`. Every subcommand takes `-h`/`--help`
+fn parse_subcommand(
+    fnq_cmd: ffi::OsString,
+    subcommand: Subcommand,
+    args: Vec<ffi::OsString>,
+    defaults: &QueueOptions,
+) -> ParseResult {
+    let mut parser = OptParser::new(args);
+    let mut signal = signal::Signal::SIGTERM;
+    let mut options = defaults.clone();
+    let mut trigger = None;
+    let mut labels = Vec::new();
+    let mut batch = None;
+
+    loop {
+        if subcommand == Subcommand::Add {
+            match parse_queue_options(&mut parser, &options) {
+                Some(parsed) => options = parsed,
+                None => return ParseResult::CommandError(subcommand),
+            }
+        }
+        let opt = match parser.next_opt() {
+            Some(opt) => opt,
+            None => break,
+        };
+        match (subcommand, opt.name.as_str()) {
+            (_, "-h") | (_, "--help") => return ParseResult::CommandHelp(subcommand),
+            (Subcommand::Kill, "-s") | (Subcommand::Kill, "--signal") => {
+                match opt.value.or_else(|| parser.value()).and_then(parse_signal) {
+                    Some(parsed) => signal = parsed,
+                    None => return ParseResult::CommandError(subcommand),
+                }
+            }
+            (Subcommand::Add, _) => {
+                if parse_trigger(&opt, &mut parser, &mut trigger).is_none() {
+                    return ParseResult::CommandError(subcommand);
+                }
+            }
+            (Subcommand::Wait, "--batch") => {
+                match opt.value.or_else(|| parser.value()).filter(|batch| !batch.is_empty()) {
+                    Some(parsed) => batch = Some(parsed),
+                    None => return ParseResult::CommandError(subcommand),
+                }
+            }
+            (Subcommand::Ls, _) => match parse_label_filter(&opt, &mut parser) {
+                Some(label) => labels.push(label),
+                None => return ParseResult::CommandError(subcommand),
+            },
+            _ => return ParseResult::CommandError(subcommand),
+        }
+    }
+
+    let mut operands = parser.operands();
+    let parsed = match subcommand {
+        Subcommand::Add => queue(fnq_cmd, operands, options, trigger),
+        Subcommand::Ls if operands.is_empty() => Some(ParseResult::List(labels)),
+        Subcommand::Wait => match (batch, operands.len()) {
+            (Some(batch), 0) => Some(ParseResult::BlockBatch(batch)),
+            (None, 0..=1) => Some(ParseResult::Block(operands.pop())),
+            _ => None,
+        },
+        Subcommand::Tail if operands.len() <= 1 => Some(ParseResult::Tail(operands.pop())),
+        Subcommand::Kill if !operands.is_empty() => Some(ParseResult::Kill(signal, operands)),
+        Subcommand::Rm if !operands.is_empty() => Some(ParseResult::Remove(operands)),
+        _ => None,
+    };
+    parsed.unwrap_or(ParseResult::CommandError(subcommand))
+}
+
+/// Accepts `TERM`, `SIGTERM` or `15`
+fn parse_signal(arg: ffi::OsString) -> Option<signal::Signal> {
+    let arg = arg.to_str()?;
+    if let Ok(number) = arg.parse::<i32>() {
+        return signal::Signal::try_from(number).ok();
+    }
+    let name = arg.to_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    format!("SIG{}", name).parse().ok()
+}
+
+/// `--batch <batch>` following `--tap` or `--block`
+fn parse_batch(args: &[ffi::OsString]) -> Option<ffi::OsString> {
+    match args {
+        [flag, batch] if flag == "--batch" && !batch.is_empty() => Some(batch.clone()),
+        _ => None,
+    }
+}
+
+/// What queues the task other than running fnq, instead of queueing it once
+enum Trigger {
+    Schedule(Schedule),
+    OnChange(Vec<path::PathBuf>),
+}
+
+/// `--every <duration>`, `--cron <expression>` or any number of `--on-change <path>`, which
+/// can't be combined
+fn parse_trigger(opt: &Opt, parser: &mut OptParser, trigger: &mut Option<Trigger>) -> Option<()> {
+    let value = match opt.name.as_str() {
+        "--every" | "--cron" | "--on-change" => opt.value.clone().or_else(|| parser.value())?,
+        _ => return None,
+    };
+    match (opt.name.as_str(), trigger) {
+        ("--every", trigger @ None) => {
+            let interval = parse_duration(&value).filter(|interval| !interval.is_zero())?;
+            *trigger = Some(Trigger::Schedule(Schedule::Every(interval)));
+        }
+        ("--cron", trigger @ None) => {
+            *trigger = Some(Trigger::Schedule(Schedule::Cron(Cron::parse(value.to_str()?)?)));
+        }
+        ("--on-change", trigger @ None) if !value.is_empty() => {
+            *trigger = Some(Trigger::OnChange(vec![value.into()]));
+        }
+        ("--on-change", Some(Trigger::OnChange(paths))) if !value.is_empty() => {
+            paths.push(value.into());
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Splits `key=value` at the first `=`. The key can't be empty
+fn parse_label(arg: ffi::OsString) -> Option<(ffi::OsString, ffi::OsString)> {
+    let bytes = arg.as_bytes();
+    let split = bytes.iter().position(|&byte| byte == b'=').filter(|&split| split > 0)?;
+    Some((
+        ffi::OsStr::from_bytes(&bytes[..split]).into(),
+        ffi::OsStr::from_bytes(&bytes[split + 1..]).into(),
+    ))
+}
+
+/// `--label key=value` of `--list` and `ls`
+fn parse_label_filter(
+    opt: &Opt,
+    parser: &mut OptParser,
+) -> Option<(ffi::OsString, ffi::OsString)> {
+    match opt.name.as_str() {
+        "--label" => parse_label(opt.value.clone().or_else(|| parser.value())?),
+        _ => None,
+    }
+}
+
+/// Parses the queue options in front of the task command, on top of `defaults`
+fn parse_queue_options(parser: &mut OptParser, defaults: &QueueOptions) -> Option<QueueOptions> {
+    let mut options = defaults.clone();
 
-    for arg in &args[1..] {
-        if arg == "--quiet" || arg == "-q" {
-            quiet = true;
-            index += 1;
-        } else if arg == "--clean" || arg == "-c" {
-            clean = true;
-            index += 1;
+    while let Some(opt) = parser.peek_opt() {
+        let takes_value = match opt.name.as_str() {
+            "-q" | "--quiet" | "--no-quiet" | "-c" | "--archive" | "-z" | "--compress" | "--unique"
+            | "--replace" | "--cgroup" => false,
+            // Takes an optional `=<when>`
+            "--clean" => opt.value.is_some(),
+            "--keep-last" | "--on-success" | "--on-failure" | "--timeout" | "--name"
+            | "--label" | "--batch" | "--lock" | "--min-memory" | "--max-load" | "--limit-mem"
+            | "--limit-cpu" | "--limit-nofile" | "--limit-core" | "--memory-max" | "--cpu-max"
+            | "--nice" | "--ionice" => true,
+            // Anything else is for the caller to handle, e.g. --help
+            _ => return Some(options),
+        };
+        let opt = parser.next_opt()?;
+        if !takes_value && opt.value.is_some() {
+            return None;
+        }
+        let value = if takes_value {
+            opt.value.or_else(|| parser.value())
         } else {
-            break;
+            None
+        };
+
+        match opt.name.as_str() {
+            "-q" | "--quiet" => options.quiet = true,
+            "--no-quiet" => options.quiet = false,
+            "-c" | "--clean" => match value {
+                None => options.clean = CleanPolicy::Success,
+                Some(policy) => options.clean = CleanPolicy::parse(policy.to_str()?)?,
+            },
+            "--keep-last" => options.keep_last = Some(value?.to_str()?.parse().ok()?),
+            "--archive" => options.archive = true,
+            "-z" | "--compress" => options.compress = true,
+            "--unique" => options.duplicates = DuplicatePolicy::Unique,
+            "--replace" => options.duplicates = DuplicatePolicy::Replace,
+            "--on-success" => options.on_success = Some(value?),
+            "--on-failure" => options.on_failure = Some(value?),
+            "--timeout" => options.timeout = parse_or_none(value?, parse_duration)?,
+            "--name" => options.name = Some(value.filter(|name| !name.is_empty())?),
+            "--label" => options.labels.push(parse_label(value?)?),
+            "--batch" => options.batch = Some(value.filter(|batch| !batch.is_empty())?),
+            "--lock" => options.locks.push(NamedLock::parse(&value?)?),
+            "--min-memory" => options.min_memory = parse_or_none(value?, parse_size)?,
+            "--max-load" => options.max_load = parse_or_none(value?, parse_positive)?,
+            "--limit-mem" => options.limits.mem = Some(parse_size(&value?)?),
+            "--limit-cpu" => options.limits.cpu = Some(parse_duration(&value?)?),
+            "--limit-nofile" => options.limits.nofile = Some(value?.to_str()?.parse().ok()?),
+            "--limit-core" => options.limits.core = Some(parse_size(&value?)?),
+            "--cgroup" => options.cgroup.enabled = true,
+            "--memory-max" => options.cgroup.memory_max = Some(parse_size(&value?)?),
+            "--cpu-max" => options.cgroup.cpu_max = Some(parse_positive(&value?)?),
+            "--nice" => options.nice = parse_or_none(value?, parse_nice)?,
+            "--ionice" => {
+                options.ionice = parse_or_none(value?, |value| IoPriority::parse(value.to_str()?))?
+            }
+            _ => unreachable!(),
         }
     }
 
-    if index < len {
-        let task_cmd = args.drain(index..index + 1).next().unwrap();
-        let task_args = args.drain(index..).collect();
-        let fnq_cmd = args.drain(0..1).next().unwrap();
-        return ParseResult::Queue(fnq_cmd, task_cmd, task_args, quiet, clean);
+    Some(options)
+}
+
+fn queue(
+    fnq_cmd: ffi::OsString,
+    mut operands: Vec<ffi::OsString>,
+    options: QueueOptions,
+    trigger: Option<Trigger>,
+) -> Option<ParseResult> {
+    if operands.is_empty() {
+        return None;
+    }
+    let task_args = operands.split_off(1);
+    let task_cmd = operands.pop()?;
+    Some(match trigger {
+        None => ParseResult::Queue(fnq_cmd, task_cmd, task_args, options),
+        Some(Trigger::Schedule(schedule)) => {
+            ParseResult::Schedule(fnq_cmd, task_cmd, task_args, options, schedule)
+        }
+        Some(Trigger::OnChange(paths)) => {
+            ParseResult::OnChange(fnq_cmd, task_cmd, task_args, options, paths)
+        }
+    })
+}
+
+struct Opt {
+    /// `-q` or `--quiet`. Combined short flags are split up, so `-qc` is returned as `-q` and `-c`
+    name: String,
+    /// From `--name=value`
+    value: Option<ffi::OsString>,
+}
+
+impl Opt {
+    fn short(short: char) -> Self {
+        Self {
+            name: format!("-{}", short),
+            value: None,
+        }
     }
+}
 
-    ParseResult::Error
+/// Splits the arguments into options and operands. Options end at the first operand or at `--`,
+/// so that everything after the task command is left to the task
+struct OptParser {
+    args: std::iter::Peekable<std::vec::IntoIter<ffi::OsString>>,
+    // Rest of a group of short flags such as `-qcz`
+    shorts: String,
+}
+
+impl OptParser {
+    fn new(args: Vec<ffi::OsString>) -> Self {
+        Self {
+            args: args.into_iter().peekable(),
+            shorts: String::new(),
+        }
+    }
+
+    fn peek_opt(&mut self) -> Option<Opt> {
+        let arg = match self.shorts.chars().next() {
+            Some(_) => return self.shorts.chars().next().map(Opt::short),
+            None => self.args.peek()?.to_str()?,
+        };
+        if arg == "--" || arg == "-" || !arg.starts_with('-') {
+            return None;
+        }
+        Some(match arg.strip_prefix("--") {
+            Some(long) => match long.split_once('=') {
+                Some((name, value)) => Opt {
+                    name: format!("--{}", name),
+                    value: Some(value.into()),
+                },
+                None => Opt {
+                    name: arg.into(),
+                    value: None,
+                },
+            },
+            None => Opt::short(arg[1..].chars().next()?),
+        })
+    }
+
+    /// Next option, or `None` once the options have ended. Consumes a `--`
+    fn next_opt(&mut self) -> Option<Opt> {
+        let opt = match self.peek_opt() {
+            Some(opt) => opt,
+            None => {
+                if self.shorts.is_empty() {
+                    self.args.next_if(|arg| arg == "--");
+                }
+                return None;
+            }
+        };
+
+        if self.shorts.is_empty() {
+            let arg = self.args.next()?;
+            if opt.name.starts_with("--") {
+                return Some(opt);
+            }
+            self.shorts = arg.to_str()?[1..].into();
+        }
+        self.shorts.remove(0);
+        Some(opt)
+    }
+
+    /// Value of the option just returned by `next_opt`: the rest of a group of short flags, as in
+    /// `-s9`, or else the next argument
+    fn value(&mut self) -> Option<ffi::OsString> {
+        if !self.shorts.is_empty() {
+            return Some(std::mem::take(&mut self.shorts).into());
+        }
+        self.args.next()
+    }
+
+    fn operands(self) -> Vec<ffi::OsString> {
+        self.args.collect()
+    }
 }
 
 #[cfg(test)]
@@ -84,68 +644,106 @@ mod tests {
     #[test]
     fn test_parse_args() {
         let mut args: Vec<ffi::OsString> = vec![];
-        assert_eq!(parse_args(args), ParseResult::Error);
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Error);
 
         args = vec_into!["fnq"];
-        assert_eq!(parse_args(args), ParseResult::Error);
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Error);
 
         args = vec_into!["fnq", "--tap"];
-        assert_eq!(parse_args(args), ParseResult::Tap(None));
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Tap(None));
 
         args = vec_into!["fnq", "-t"];
-        assert_eq!(parse_args(args), ParseResult::Tap(None));
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Tap(None));
 
         args = vec_into!["fnq", "--quiet"];
-        assert_eq!(parse_args(args), ParseResult::Error);
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Error);
 
         args = vec_into!["fnq", "-q"];
-        assert_eq!(parse_args(args), ParseResult::Error);
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Error);
 
         args = vec_into!["fnq", "--clean"];
-        assert_eq!(parse_args(args), ParseResult::Error);
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Error);
 
         args = vec_into!["fnq", "-c"];
-        assert_eq!(parse_args(args), ParseResult::Error);
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Error);
 
         args = vec_into!["fnq", "--block"];
-        assert_eq!(parse_args(args), ParseResult::Block(None));
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Block(None));
 
         args = vec_into!["fnq", "-b"];
-        assert_eq!(parse_args(args), ParseResult::Block(None));
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Block(None));
 
         args = vec_into!["fnq", "--block", "queue_file.pid"];
         assert_eq!(
-            parse_args(args),
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Block(Some("queue_file.pid".into()))
+        );
+
+        args = vec_into!["fnq", "--wait", "queue_file.pid"];
+        assert_eq!(
+            parse_args(args, &QueueOptions::default()),
             ParseResult::Block(Some("queue_file.pid".into()))
         );
 
         args = vec_into!["fnq", "-b", "queue_file.pid"];
         assert_eq!(
-            parse_args(args),
+            parse_args(args, &QueueOptions::default()),
             ParseResult::Block(Some("queue_file.pid".into()))
         );
 
         args = vec_into!["fnq", "--quiet", "sleep", "2"];
         assert_eq!(
-            parse_args(args),
-            ParseResult::Queue("fnq".into(), "sleep".into(), vec_into!["2"], true, false)
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    quiet: true,
+                    ..QueueOptions::default()
+                }
+            )
         );
         args = vec_into!["fnq", "-q", "sleep", "2"];
         assert_eq!(
-            parse_args(args),
-            ParseResult::Queue("fnq".into(), "sleep".into(), vec_into!["2"], true, false)
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    quiet: true,
+                    ..QueueOptions::default()
+                }
+            )
         );
 
         args = vec_into!["fnq", "--clean", "sleep", "2"];
         assert_eq!(
-            parse_args(args),
-            ParseResult::Queue("fnq".into(), "sleep".into(), vec_into!["2"], false, true)
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    clean: CleanPolicy::Success,
+                    ..QueueOptions::default()
+                }
+            )
         );
 
         args = vec_into!["fnq", "-c", "sleep", "2"];
         assert_eq!(
-            parse_args(args),
-            ParseResult::Queue("fnq".into(), "sleep".into(), vec_into!["2"], false, true)
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    clean: CleanPolicy::Success,
+                    ..QueueOptions::default()
+                }
+            )
         );
 
         args = vec_into![
@@ -156,8 +754,17 @@ mod tests {
             "2",
         ];
         assert_eq!(
-            parse_args(args),
-            ParseResult::Queue("fnq".into(), "sleep".into(), vec_into!["2"], true, true)
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    quiet: true,
+                    clean: CleanPolicy::Success,
+                    ..QueueOptions::default()
+                }
+            )
         );
 
         args = vec_into![
@@ -168,18 +775,93 @@ mod tests {
             "2",
         ];
         assert_eq!(
-            parse_args(args),
-            ParseResult::Queue("fnq".into(), "sleep".into(), vec_into!["2"], true, true)
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    quiet: true,
+                    clean: CleanPolicy::Success,
+                    ..QueueOptions::default()
+                }
+            )
         );
 
         args = vec_into!["fnq", "sleep"];
         assert_eq!(
-            parse_args(args),
-            ParseResult::Queue("fnq".into(), "sleep".into(), vec!(), false, false)
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec!(),
+                QueueOptions::default()
+            )
+        );
+
+        args = vec_into![
+            "fnq",
+            "--clean=failure",
+            "--keep-last",
+            "10",
+            "--archive",
+            "sleep",
+            "2",
+        ];
+        assert_eq!(
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    clean: CleanPolicy::Failure,
+                    keep_last: Some(10),
+                    archive: true,
+                    ..QueueOptions::default()
+                }
+            )
+        );
+
+        args = vec_into!["fnq", "-z", "sleep", "2"];
+        assert_eq!(
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    compress: true,
+                    ..QueueOptions::default()
+                }
+            )
+        );
+
+        args = vec_into!["fnq", "--clean=always", "sleep", "2"];
+        assert_eq!(
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["2"],
+                QueueOptions {
+                    clean: CleanPolicy::Always,
+                    ..QueueOptions::default()
+                }
+            )
+        );
+
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--clean=sometimes", "sleep", "2"], &QueueOptions::default()),
+            ParseResult::Error
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--keep-last", "sleep", "2"], &QueueOptions::default()),
+            ParseResult::Error
         );
 
         args = vec_into!["fnq", "--tap"];
-        assert_eq!(parse_args(args), ParseResult::Tap(None));
+        assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Tap(None));
 
         args = vec_into![
             "fnq",
@@ -187,52 +869,736 @@ mod tests {
             ffi::OsString::from("queue_file.pid"),
         ];
         assert_eq!(
-            parse_args(args),
+            parse_args(args, &QueueOptions::default()),
             ParseResult::Tap(Some("queue_file.pid".into()))
         );
 
         assert_eq!(
-            parse_args(vec_into!["fnq", "--version"]),
+            parse_args(vec_into!["fnq", "--version"], &QueueOptions::default()),
             ParseResult::Version
         );
         assert_eq!(
-            parse_args(vec_into!["fnq", "-v"]),
+            parse_args(vec_into!["fnq", "-v"], &QueueOptions::default()),
             ParseResult::Version
         );
         assert_eq!(
-            parse_args(vec_into!["fnq", "-v", "somethingelse"]),
+            parse_args(vec_into!["fnq", "-v", "somethingelse"], &QueueOptions::default()),
             ParseResult::Version
         );
 
         assert_eq!(
-            parse_args(vec_into!["fnq", "--help"]),
+            parse_args(vec_into!["fnq", "--help"], &QueueOptions::default()),
             ParseResult::Help
         );
         assert_eq!(
-            parse_args(vec_into!["fnq", "-h"]),
+            parse_args(vec_into!["fnq", "-h"], &QueueOptions::default()),
             ParseResult::Help
         );
         assert_eq!(
-            parse_args(vec_into!["fnq", "-h", "somethingelse"]),
+            parse_args(vec_into!["fnq", "-h", "somethingelse"], &QueueOptions::default()),
             ParseResult::Help
         );
 
         assert_eq!(
-            parse_args(vec_into!["fnq", "--watch"]),
+            parse_args(vec_into!["fnq", "--watch"], &QueueOptions::default()),
             ParseResult::Watch
         );
         assert_eq!(
-            parse_args(vec_into!["fnq", "-w"]),
+            parse_args(vec_into!["fnq", "-w"], &QueueOptions::default()),
             ParseResult::Watch
         );
 
         assert_eq!(
-            parse_args(vec_into!["fnq", "--last"]),
+            parse_args(vec_into!["fnq", "--last"], &QueueOptions::default()),
             ParseResult::Last
         );
         assert_eq!(
-            parse_args(vec_into!["fnq", "-l"]),
+            parse_args(vec_into!["fnq", "-l"], &QueueOptions::default()),
             ParseResult::Last
         );
+
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--gc"], &QueueOptions::default()),
+            ParseResult::Gc(GcPolicy::default())
+        );
+        assert_eq!(
+            parse_args(vec_into![
+                "fnq",
+                "--gc",
+                "--older-than",
+                "7d",
+                "--keep-last",
+                "100",
+                "--only-succeeded",
+                "--archive",
+            ], &QueueOptions::default()),
+            ParseResult::Gc(GcPolicy {
+                older_than: Some(time::Duration::from_secs(7 * 24 * 60 * 60)),
+                keep_last: Some(100),
+                only_succeeded: true,
+                archive: true,
+            })
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--gc", "--keep-last"], &QueueOptions::default()),
+            ParseResult::Error
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--gc", "--older-than", "soon"], &QueueOptions::default()),
+            ParseResult::Error
+        );
+    }
+
+    #[test]
+    fn test_parse_repair() {
+        assert_eq!(parse_args(vec_into!["fnq", "--list"], &QueueOptions::default()), ParseResult::List(vec!()));
+        assert_eq!(parse_args(vec_into!["fnq", "--events"], &QueueOptions::default()), ParseResult::Events);
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--repair"], &QueueOptions::default()),
+            ParseResult::Repair(false)
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--repair", "--requeue"], &QueueOptions::default()),
+            ParseResult::Repair(true)
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--repair", "--later"], &QueueOptions::default()),
+            ParseResult::Error
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--resume"], &QueueOptions::default()),
+            ParseResult::Repair(true)
+        );
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        assert_eq!(
+            parse_args(vec_into!["fnq", "add", "-qcz", "--", "-x", "--help"], &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "-x".into(),
+                vec_into!["--help"],
+                QueueOptions {
+                    quiet: true,
+                    clean: CleanPolicy::Success,
+                    compress: true,
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "add", "--on-success=echo ok", "sleep", "-q"], &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "sleep".into(),
+                vec_into!["-q"],
+                QueueOptions {
+                    on_success: Some("echo ok".into()),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "add", "-q"], &QueueOptions::default()),
+            ParseResult::CommandError(Subcommand::Add)
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "add", "-qx", "sleep"], &QueueOptions::default()),
+            ParseResult::CommandError(Subcommand::Add)
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "add", "--quiet=yes", "sleep"], &QueueOptions::default()),
+            ParseResult::CommandError(Subcommand::Add)
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "add", "-q", "--help"], &QueueOptions::default()),
+            ParseResult::CommandHelp(Subcommand::Add)
+        );
+
+        assert_eq!(parse_args(vec_into!["fnq", "ls"], &QueueOptions::default()), ParseResult::List(vec!()));
+        assert_eq!(
+            parse_args(vec_into!["fnq", "ls", "fnq1.2"], &QueueOptions::default()),
+            ParseResult::CommandError(Subcommand::Ls)
+        );
+        assert_eq!(parse_args(vec_into!["fnq", "wait"], &QueueOptions::default()), ParseResult::Block(None));
+        assert_eq!(
+            parse_args(vec_into!["fnq", "wait", "fnq1.2"], &QueueOptions::default()),
+            ParseResult::Block(Some("fnq1.2".into()))
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "tail", "--", "fnq1.2"], &QueueOptions::default()),
+            ParseResult::Tail(Some("fnq1.2".into()))
+        );
+
+        assert_eq!(
+            parse_args(vec_into!["fnq", "kill", "fnq1.2", "fnq1.3"], &QueueOptions::default()),
+            ParseResult::Kill(signal::Signal::SIGTERM, vec_into!["fnq1.2", "fnq1.3"])
+        );
+        for signal_args in [vec!["-s", "KILL"], vec!["-s9"], vec!["--signal", "sigkill"]] {
+            let mut args: Vec<ffi::OsString> = vec_into!["fnq", "kill"];
+            args.extend(signal_args.into_iter().map(ffi::OsString::from));
+            args.push("fnq1.2".into());
+            assert_eq!(
+                parse_args(args, &QueueOptions::default()),
+                ParseResult::Kill(signal::Signal::SIGKILL, vec_into!["fnq1.2"])
+            );
+        }
+        assert_eq!(
+            parse_args(vec_into!["fnq", "kill", "--signal=NOPE", "fnq1.2"], &QueueOptions::default()),
+            ParseResult::CommandError(Subcommand::Kill)
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "kill"], &QueueOptions::default()),
+            ParseResult::CommandError(Subcommand::Kill)
+        );
+
+        assert_eq!(
+            parse_args(vec_into!["fnq", "rm", "fnq1.2", "fnq1.3"], &QueueOptions::default()),
+            ParseResult::Remove(vec_into!["fnq1.2", "fnq1.3"])
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "rm", "-h"], &QueueOptions::default()),
+            ParseResult::CommandHelp(Subcommand::Rm)
+        );
+        assert_eq!(parse_args(vec_into!["fnq", "help"], &QueueOptions::default()), ParseResult::Help);
+        assert_eq!(
+            parse_args(vec_into!["fnq", "help", "tail"], &QueueOptions::default()),
+            ParseResult::CommandHelp(Subcommand::Tail)
+        );
+
+        // The legacy form takes the same flags
+        assert_eq!(
+            parse_args(vec_into!["fnq", "-qz", "--keep-last=3", "--", "ls"], &QueueOptions::default()),
+            ParseResult::Queue(
+                "fnq".into(),
+                "ls".into(),
+                vec!(),
+                QueueOptions {
+                    quiet: true,
+                    compress: true,
+                    keep_last: Some(3),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        // A task named like a subcommand, as requeued
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--", "ls", "-la"], &QueueOptions::default()),
+            ParseResult::Queue("fnq".into(), "ls".into(), vec_into!["-la"], QueueOptions::default())
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--unknown", "sleep"], &QueueOptions::default()),
+            ParseResult::Error
+        );
+    }
+
+    #[test]
+    fn test_parse_labels() {
+        assert_eq!(
+            parse_args(
+                vec_into!["fnq", "--name", "docs", "--label", "team=web", "--label=a==", "make"],
+                &QueueOptions::default()
+            ),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    name: Some("docs".into()),
+                    labels: vec![("team".into(), "web".into()), ("a".into(), "=".into())],
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        for args in [
+            vec!["fnq", "--label", "=web", "make"],
+            vec!["fnq", "--label", "team", "make"],
+            vec!["fnq", "--name", "", "make"],
+        ] {
+            let args = args.into_iter().map(ffi::OsString::from).collect();
+            assert_eq!(parse_args(args, &QueueOptions::default()), ParseResult::Error);
+        }
+
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--list", "--label", "team=web"], &QueueOptions::default()),
+            ParseResult::List(vec![("team".into(), "web".into())])
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "ls", "--label=team=web", "--label", "x="], &QueueOptions::default()),
+            ParseResult::List(vec![("team".into(), "web".into()), ("x".into(), "".into())])
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--list", "--label"], &QueueOptions::default()),
+            ParseResult::Error
+        );
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let parse = |args: Vec<&str>| {
+            parse_args(
+                args.into_iter().map(ffi::OsString::from).collect(),
+                &QueueOptions::default(),
+            )
+        };
+        assert_eq!(parse(vec!["fnq", "--batch-start"]), ParseResult::BatchStart);
+        assert_eq!(
+            parse(vec!["fnq", "--batch", "b1", "make"]),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    batch: Some("b1".into()),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(
+            parse(vec!["fnq", "--tap", "--batch", "b1"]),
+            ParseResult::TapBatch("b1".into())
+        );
+        assert_eq!(
+            parse(vec!["fnq", "--wait", "--batch", "b1"]),
+            ParseResult::BlockBatch("b1".into())
+        );
+        assert_eq!(
+            parse(vec!["fnq", "wait", "--batch=b1"]),
+            ParseResult::BlockBatch("b1".into())
+        );
+        assert_eq!(
+            parse(vec!["fnq", "wait", "--batch", "b1", "fnq1.2"]),
+            ParseResult::CommandError(Subcommand::Wait)
+        );
+        assert_eq!(parse(vec!["fnq", "--block", "--batch"]), ParseResult::Block(Some("--batch".into())));
+        assert_eq!(parse(vec!["fnq", "--tap", "--batch", ""]), ParseResult::Error);
+    }
+
+    #[test]
+    fn test_parse_barrier() {
+        let parse = |args: Vec<&str>| {
+            parse_args(
+                args.into_iter().map(ffi::OsString::from).collect(),
+                &QueueOptions::default(),
+            )
+        };
+        assert_eq!(
+            parse(vec!["fnq", "--barrier"]),
+            ParseResult::Barrier("fnq".into(), QueueOptions::default())
+        );
+        assert_eq!(
+            parse(vec!["fnq", "-q", "--name", "phase1", "--barrier"]),
+            ParseResult::Barrier(
+                "fnq".into(),
+                QueueOptions {
+                    quiet: true,
+                    name: Some("phase1".into()),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(parse(vec!["fnq", "--barrier", "make"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--barrier", "-q"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--barrier=yes"]), ParseResult::Error);
+        assert_eq!(
+            parse(vec!["fnq", "--", "--barrier"]),
+            ParseResult::Queue("fnq".into(), "--barrier".into(), vec!(), QueueOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        let parse = |args: Vec<&str>| {
+            parse_args(
+                args.into_iter().map(ffi::OsString::from).collect(),
+                &QueueOptions::default(),
+            )
+        };
+        let hourly = Schedule::Every(time::Duration::from_secs(60 * 60));
+        let quiet = QueueOptions {
+            quiet: true,
+            ..QueueOptions::default()
+        };
+        assert_eq!(
+            parse(vec!["fnq", "-q", "--every", "1h", "./sync.sh"]),
+            ParseResult::Schedule("fnq".into(), "./sync.sh".into(), vec!(), quiet.clone(), hourly.clone())
+        );
+        assert_eq!(
+            parse(vec!["fnq", "add", "--every=1h", "-q", "./sync.sh"]),
+            ParseResult::Schedule("fnq".into(), "./sync.sh".into(), vec!(), quiet, hourly)
+        );
+        assert_eq!(
+            parse(vec!["fnq", "--cron", "*/5 * * * *", "--", "make", "-k"]),
+            ParseResult::Schedule(
+                "fnq".into(),
+                "make".into(),
+                vec_into!["-k"],
+                QueueOptions::default(),
+                Schedule::Cron(Cron::parse("*/5 * * * *").unwrap())
+            )
+        );
+        assert_eq!(parse(vec!["fnq", "--every", "0s", "true"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--cron", "* * *", "true"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--every", "1h", "--every", "2h", "true"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--every", "1h", "--barrier"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--every", "1h"]), ParseResult::Error);
+
+        assert_eq!(
+            parse(vec!["fnq", "--on-change", "src/", "--on-change=Cargo.toml", "--", "cargo", "test"]),
+            ParseResult::OnChange(
+                "fnq".into(),
+                "cargo".into(),
+                vec_into!["test"],
+                QueueOptions::default(),
+                vec_into!["src/", "Cargo.toml"]
+            )
+        );
+        assert_eq!(
+            parse(vec!["fnq", "add", "-q", "--on-change", "src", "make"]),
+            ParseResult::OnChange(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    quiet: true,
+                    ..QueueOptions::default()
+                },
+                vec_into!["src"]
+            )
+        );
+        assert_eq!(parse(vec!["fnq", "--on-change", "src", "--every", "1h", "make"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--on-change", "", "make"]), ParseResult::Error);
+
+        assert_eq!(parse(vec!["fnq", "--schedules"]), ParseResult::Schedules);
+        assert_eq!(
+            parse(vec!["fnq", "--unschedule", "schedule1.2"]),
+            ParseResult::Unschedule(vec_into!["schedule1.2"])
+        );
+        assert_eq!(parse(vec!["fnq", "--unschedule"]), ParseResult::Error);
+    }
+
+    #[test]
+    fn test_parse_duplicates() {
+        let parse = |args: Vec<&str>| {
+            parse_args(
+                args.into_iter().map(ffi::OsString::from).collect(),
+                &QueueOptions::default(),
+            )
+        };
+        assert_eq!(
+            parse(vec!["fnq", "--unique", "make"]),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    duplicates: DuplicatePolicy::Unique,
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(
+            parse(vec!["fnq", "add", "--unique", "--replace", "make"]),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    duplicates: DuplicatePolicy::Replace,
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(parse(vec!["fnq", "--unique=yes", "make"]), ParseResult::Error);
+    }
+
+    #[test]
+    fn test_parse_locks() {
+        let parse = |args: Vec<&str>| {
+            parse_args(
+                args.into_iter().map(ffi::OsString::from).collect(),
+                &QueueOptions::default(),
+            )
+        };
+        assert_eq!(
+            parse(vec!["fnq", "--lock", "db", "--lock=gpu:2", "make"]),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    locks: vec![
+                        NamedLock::new("db", 1).unwrap(),
+                        NamedLock::new("gpu", 2).unwrap()
+                    ],
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(parse(vec!["fnq", "--lock", "gpu:0", "make"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--lock", "a/b", "make"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--lock"]), ParseResult::Error);
+    }
+
+    #[test]
+    fn test_parse_priority() {
+        let parse = |args: Vec<&str>| {
+            parse_args(
+                args.into_iter().map(ffi::OsString::from).collect(),
+                &QueueOptions::default(),
+            )
+        };
+        assert_eq!(
+            parse(vec!["fnq", "--nice", "-5", "--ionice=best-effort:7", "make"]),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    nice: Some(-5),
+                    ionice: IoPriority::parse("best-effort:7"),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(parse(vec!["fnq", "--nice", "20", "make"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--ionice", "low", "make"]), ParseResult::Error);
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        use crate::ops::Limits;
+
+        let parse = |args: Vec<&str>| {
+            parse_args(
+                args.into_iter().map(ffi::OsString::from).collect(),
+                &QueueOptions::default(),
+            )
+        };
+        let limits = Limits {
+            mem: Some(2 << 30),
+            cpu: Some(time::Duration::from_secs(90 * 60)),
+            nofile: Some(256),
+            core: Some(0),
+        };
+        assert_eq!(
+            parse(vec![
+                "fnq",
+                "--limit-mem=2G",
+                "--limit-cpu",
+                "90m",
+                "--limit-nofile",
+                "256",
+                "--limit-core",
+                "0",
+                "make"
+            ]),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    limits: limits.clone(),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        let args: Vec<ffi::OsString> = vec_into![
+            "--limit-mem",
+            "2147483648",
+            "--limit-cpu",
+            "5400s",
+            "--limit-nofile",
+            "256",
+            "--limit-core",
+            "0"
+        ];
+        assert_eq!(limits.to_args(), args);
+        assert_eq!(parse(vec!["fnq", "--limit-nofile", "many", "make"]), ParseResult::Error);
+    }
+
+    #[test]
+    fn test_parse_cgroup_limits() {
+        use crate::ops::CgroupLimits;
+
+        let parse = |args: Vec<&str>| {
+            parse_args(
+                args.into_iter().map(ffi::OsString::from).collect(),
+                &QueueOptions::default(),
+            )
+        };
+        let cgroup = CgroupLimits {
+            enabled: false,
+            memory_max: Some(512 << 20),
+            cpu_max: Some(1.5),
+        };
+        assert_eq!(
+            parse(vec!["fnq", "--memory-max", "512M", "--cpu-max=1.5", "make"]),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    cgroup: cgroup.clone(),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        let args: Vec<ffi::OsString> = vec_into!["--memory-max", "536870912", "--cpu-max", "1.5"];
+        assert_eq!(cgroup.to_args(), args);
+        assert!(cgroup.is_enabled());
+
+        let cgroup = CgroupLimits {
+            enabled: true,
+            ..CgroupLimits::default()
+        };
+        assert_eq!(
+            parse(vec!["fnq", "--cgroup", "make"]),
+            ParseResult::Queue(
+                "fnq".into(),
+                "make".into(),
+                vec!(),
+                QueueOptions {
+                    cgroup: cgroup.clone(),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(cgroup.to_args(), vec_into!["--cgroup"] as Vec<ffi::OsString>);
+        assert!(!CgroupLimits::default().is_enabled());
+        assert_eq!(parse(vec!["fnq", "--cpu-max", "0", "make"]), ParseResult::Error);
+        assert_eq!(parse(vec!["fnq", "--memory-max", "lots", "make"]), ParseResult::Error);
+    }
+
+    #[test]
+    fn test_parse_global_options() {
+        let mut args: Vec<ffi::OsString> =
+            vec_into!["fnq", "--dir", "/tmp/q", "--queue=builds", "-q", "--dir", "ls"];
+        assert_eq!(
+            parse_global_options(&mut args),
+            Some(GlobalOptions {
+                dir: Some("/tmp/q".into()),
+                queue: Some("builds".into()),
+            })
+        );
+        let remaining: Vec<ffi::OsString> = vec_into!["fnq", "-q", "--dir", "ls"];
+        assert_eq!(args, remaining);
+
+        let mut args: Vec<ffi::OsString> = vec_into!["fnq", "--dir"];
+        assert_eq!(parse_global_options(&mut args), None);
+
+        // Flags of the queued command start out from the defaults
+        let defaults = QueueOptions {
+            quiet: true,
+            clean: CleanPolicy::Always,
+            timeout: Some(time::Duration::from_secs(60)),
+            ..QueueOptions::default()
+        };
+        assert_eq!(
+            parse_args(vec_into!["fnq", "add", "--clean=never", "--timeout", "2m", "true"], &defaults),
+            ParseResult::Queue(
+                "fnq".into(),
+                "true".into(),
+                vec!(),
+                QueueOptions {
+                    quiet: true,
+                    clean: CleanPolicy::Never,
+                    timeout: Some(time::Duration::from_secs(120)),
+                    ..QueueOptions::default()
+                }
+            )
+        );
+        assert_eq!(
+            parse_args(vec_into!["fnq", "--no-quiet", "--timeout=none", "true"], &defaults),
+            ParseResult::Queue(
+                "fnq".into(),
+                "true".into(),
+                vec!(),
+                QueueOptions {
+                    clean: CleanPolicy::Always,
+                    ..QueueOptions::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_queue_options_to_args() {
+        let options = QueueOptions {
+            clean: CleanPolicy::Failure,
+            keep_last: Some(3),
+            archive: true,
+            compress: true,
+            on_success: Some("notify-send 'it worked'".into()),
+            on_failure: Some("fnq ./retry.sh".into()),
+            timeout: Some(time::Duration::from_secs(90)),
+            name: Some("nightly build".into()),
+            labels: vec![("team".into(), "docs".into()), ("env".into(), "a=b".into())],
+            batch: Some("release".into()),
+            ..QueueOptions::default()
+        };
+        let mut args: Vec<ffi::OsString> = vec_into!["fnq"];
+        args.extend(options.to_args());
+        args.push("true".into());
+        assert_eq!(
+            parse_args(args, &QueueOptions::default()),
+            ParseResult::Queue("fnq".into(), "true".into(), vec!(), options)
+        );
+
+        // Replayed on top of the config file's defaults, unset options stay unset
+        let defaults = QueueOptions {
+            clean: CleanPolicy::Always,
+            timeout: Some(time::Duration::from_secs(60)),
+            min_memory: Some(4 << 30),
+            max_load: Some(8.0),
+            nice: Some(10),
+            ionice: IoPriority::parse("idle"),
+            ..QueueOptions::default()
+        };
+        let options = QueueOptions {
+            nice: Some(5),
+            ..QueueOptions::default()
+        };
+        let mut args: Vec<ffi::OsString> = vec_into!["fnq"];
+        args.extend(options.to_replay_args());
+        args.push("true".into());
+        assert_eq!(
+            parse_args(args, &defaults),
+            ParseResult::Queue("fnq".into(), "true".into(), vec!(), options)
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let parse = |arg: &str| parse_duration(ffi::OsStr::new(arg));
+        assert_eq!(parse("90"), Some(time::Duration::from_secs(90)));
+        assert_eq!(parse("45s"), Some(time::Duration::from_secs(45)));
+        assert_eq!(parse("30m"), Some(time::Duration::from_secs(30 * 60)));
+        assert_eq!(parse("12h"), Some(time::Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse("2w"), Some(time::Duration::from_secs(14 * 24 * 60 * 60)));
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("d"), None);
+        assert_eq!(parse("-1d"), None);
+        assert_eq!(parse("7y"), None);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        let parse = |arg: &str| parse_size(ffi::OsStr::new(arg));
+        assert_eq!(parse("4096"), Some(4096));
+        assert_eq!(parse("512K"), Some(512 * 1024));
+        assert_eq!(parse("256m"), Some(256 * 1024 * 1024));
+        assert_eq!(parse("4G"), Some(4 * 1024 * 1024 * 1024));
+        assert_eq!(parse("G"), None);
+        assert_eq!(parse("1.5G"), None);
+        assert_eq!(parse("99999999T"), None);
+
+        let parse = |arg: &str| parse_positive(ffi::OsStr::new(arg));
+        assert_eq!(parse("1.5"), Some(1.5));
+        assert_eq!(parse("8"), Some(8.0));
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("inf"), None);
+        assert_eq!(parse("high"), None);
     }
 }
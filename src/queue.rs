@@ -0,0 +1,297 @@
+use std::os::unix::prelude::*;
+use std::{env, ffi, fs, io, path, process, time};
+
+use crate::ops::{
+    self, CgroupLimits, CleanPolicy, DuplicatePolicy, Events, IOResultExt, IoPriority, Job, JobId,
+    JobState, JobStatus, Limits, NamedLock, OpsError, QueueOptions,
+};
+
+// Recorded as the fnq command on the exec line, so it can still be pasted into a shell
+const FND_CMD: &str = "fnq";
+
+/// Overrides where the fnq binary that queues tasks is, which is otherwise looked up in `$PATH`
+const FNQ_BIN_VAR: &str = "FNQ_BIN";
+
+/// A task to queue, built like `std::process::Command`
+#[derive(Debug, Clone)]
+pub struct Command {
+    program: ffi::OsString,
+    args: Vec<ffi::OsString>,
+    options: QueueOptions,
+}
+
+impl Command {
+    pub fn new<S: AsRef<ffi::OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().into(),
+            args: Vec::new(),
+            options: QueueOptions::default(),
+        }
+    }
+
+    pub fn arg<S: AsRef<ffi::OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().into());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<ffi::OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().into()));
+        self
+    }
+
+    /// Which exit outcomes remove the queue file once the task completes. Same as `--clean=<when>`
+    pub fn clean(&mut self, clean: CleanPolicy) -> &mut Self {
+        self.options.clean = clean;
+        self
+    }
+
+    /// Keeps only this many finished queue files once the task completes. Same as `--keep-last`
+    pub fn keep_last(&mut self, keep_last: usize) -> &mut Self {
+        self.options.keep_last = Some(keep_last);
+        self
+    }
+
+    /// Moves removed queue files gzipped into the archive dir. Same as `--archive`
+    pub fn archive(&mut self, archive: bool) -> &mut Self {
+        self.options.archive = archive;
+        self
+    }
+
+    /// Gzips the queue file once the task completes. Same as `--compress`
+    pub fn compress(&mut self, compress: bool) -> &mut Self {
+        self.options.compress = compress;
+        self
+    }
+
+    /// Shell command to run once the task exits with status 0. Same as `--on-success`
+    pub fn on_success<S: AsRef<ffi::OsStr>>(&mut self, command: S) -> &mut Self {
+        self.options.on_success = Some(command.as_ref().into());
+        self
+    }
+
+    /// Shell command to run once the task did not succeed. Same as `--on-failure`
+    pub fn on_failure<S: AsRef<ffi::OsStr>>(&mut self, command: S) -> &mut Self {
+        self.options.on_failure = Some(command.as_ref().into());
+        self
+    }
+
+    /// Kills the task with SIGTERM, then SIGKILL, once it has run for this long. Same as
+    /// `--timeout`
+    pub fn timeout(&mut self, timeout: time::Duration) -> &mut Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    /// Name to refer to the job by, e.g. with `Queue::find`. Same as `--name`
+    pub fn name<S: AsRef<ffi::OsStr>>(&mut self, name: S) -> &mut Self {
+        self.options.name = Some(name.as_ref().into());
+        self
+    }
+
+    /// Adds the job to a group that can be waited on together. Same as `--batch`
+    pub fn batch<S: AsRef<ffi::OsStr>>(&mut self, batch: S) -> &mut Self {
+        self.options.batch = Some(batch.as_ref().into());
+        self
+    }
+
+    /// What to do if an identical task (same program, arguments and cwd) is already waiting.
+    /// Same as `--unique` or `--replace`. With `DuplicatePolicy::Unique`, `Queue::enqueue`
+    /// returns the id of the waiting task instead
+    pub fn duplicates(&mut self, duplicates: DuplicatePolicy) -> &mut Self {
+        self.options.duplicates = duplicates;
+        self
+    }
+
+    /// Holds `lock` while the task runs, so that tasks sharing it never run at the same time, even
+    /// across queues. Same as `--lock`
+    pub fn lock(&mut self, lock: NamedLock) -> &mut Self {
+        self.options.locks.push(lock);
+        self
+    }
+
+    /// Once its turn has come, holds the task back until this many bytes of memory are
+    /// available. Same as `--min-memory`
+    pub fn min_memory(&mut self, bytes: u64) -> &mut Self {
+        self.options.min_memory = Some(bytes);
+        self
+    }
+
+    /// Once its turn has come, holds the task back until the 1 minute load average is below
+    /// `load`. Same as `--max-load`
+    pub fn max_load(&mut self, load: f64) -> &mut Self {
+        self.options.max_load = Some(load);
+        self
+    }
+
+    /// Resource limits applied to the task with `setrlimit`. Same as `--limit-mem`, `--limit-cpu`,
+    /// `--limit-nofile` and `--limit-core`
+    pub fn limits(&mut self, limits: Limits) -> &mut Self {
+        self.options.limits = limits;
+        self
+    }
+
+    /// Limits enforced by the task's cgroup. Same as `--memory-max` and `--cpu-max`
+    pub fn cgroup_limits(&mut self, cgroup: CgroupLimits) -> &mut Self {
+        self.options.cgroup = cgroup;
+        self
+    }
+
+    /// Nice value to run the task with, from -20 to 19. Same as `--nice`
+    pub fn nice(&mut self, nice: i32) -> &mut Self {
+        self.options.nice = Some(nice);
+        self
+    }
+
+    /// IO scheduling class and level to run the task with. Same as `--ionice`
+    pub fn ionice(&mut self, ionice: IoPriority) -> &mut Self {
+        self.options.ionice = Some(ionice);
+        self
+    }
+
+    /// Adds a label that listings can be filtered by. Same as `--label key=value`
+    pub fn label<K: AsRef<ffi::OsStr>, V: AsRef<ffi::OsStr>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.options
+            .labels
+            .push((key.as_ref().into(), value.as_ref().into()));
+        self
+    }
+}
+
+/// Handle on a queue directory, the same one the fnq binary uses through `$FNQ_DIR`
+#[derive(Debug, Clone)]
+pub struct Queue {
+    dir: path::PathBuf,
+}
+
+impl Queue {
+    /// Opens the queue in `dir`, creating the directory if it doesn't exist yet
+    pub fn open<P: Into<path::PathBuf>>(dir: P) -> Result<Self, OpsError> {
+        let dir = dir.into();
+        if !dir.exists() {
+            // TODO: change to correct permissions? (0777)
+            fs::create_dir(&dir).path_context("create queue dir", &dir)?;
+        } else if !dir.is_dir() {
+            return Err(OpsError::PathIO(
+                "use queue dir",
+                dir,
+                io::Error::from_raw_os_error(nix::libc::ENOTDIR),
+            ));
+        }
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &path::Path {
+        &self.dir
+    }
+
+    pub(crate) fn job_path(&self, id: &JobId) -> Result<path::PathBuf, OpsError> {
+        let job_path = self.dir.join(id.as_os_str());
+        if !job_path.exists() {
+            return Err(OpsError::FileNotFound(job_path.into()));
+        }
+        Ok(job_path)
+    }
+
+    /// Queues `command` behind every task already in the queue. Returns once the task has taken
+    /// its place in the queue, while a detached process waits for its turn to run it.
+    ///
+    /// The task is queued by the fnq binary, `$FNQ_BIN` or else `fnq` in `$PATH`, as forking the
+    /// calling process isn't safe in a multithreaded one. It runs with the calling process's
+    /// working directory and environment. The config file's defaults don't apply
+    pub fn enqueue(&self, command: &Command) -> Result<JobId, OpsError> {
+        self.run_fnq(&command.options, Some(command))
+    }
+
+    /// Queues a barrier: jobs queued after it wait until every job queued before it has finished.
+    /// Its status is exit code 0 if all jobs since the previous barrier succeeded, and 1 otherwise
+    pub fn enqueue_barrier(&self) -> Result<JobId, OpsError> {
+        self.run_fnq(&QueueOptions::default(), None)
+    }
+
+    /// Runs the fnq binary to queue `task` with `options`, or a barrier without one, and returns
+    /// the queue file name it prints
+    fn run_fnq(&self, options: &QueueOptions, task: Option<&Command>) -> Result<JobId, OpsError> {
+        let fnq_bin = env::var_os(FNQ_BIN_VAR).unwrap_or_else(|| FND_CMD.into());
+        // Explicit about everything the config file could otherwise fill in
+        let mut args: Vec<ffi::OsString> =
+            vec!["--dir".into(), self.dir.clone().into(), "--no-quiet".into()];
+        match options.duplicates {
+            DuplicatePolicy::Allow => {}
+            DuplicatePolicy::Unique => args.push("--unique".into()),
+            DuplicatePolicy::Replace => args.push("--replace".into()),
+        }
+        args.extend(options.to_replay_args());
+        match task {
+            Some(command) => {
+                args.push("--".into());
+                args.push(command.program.clone());
+                args.extend(command.args.iter().cloned());
+            }
+            None => args.push("--barrier".into()),
+        }
+
+        let output = process::Command::new(&fnq_bin)
+            .arg0(FND_CMD)
+            .args(args)
+            // Garbage collection is up to the caller, as it is for the rest of the library
+            .env_remove("FNQ_GC")
+            .stdin(process::Stdio::null())
+            .output()
+            .path_context("run", path::Path::new(&fnq_bin))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr.trim_end();
+            let message = message.strip_prefix("Error: ").unwrap_or(message);
+            // The binary's exit codes for these, see EXIT CODES in its help
+            return Err(match (output.status.code(), task) {
+                (Some(126), Some(command)) => OpsError::NotExecutable(command.program.clone()),
+                (Some(127), Some(command)) => OpsError::CommandNotFound(command.program.clone()),
+                _ => OpsError::Unknown(message.into()),
+            });
+        }
+        let queue_file = output.stdout.trim_ascii_end().to_vec();
+        Ok(JobId::from(ffi::OsString::from_vec(queue_file)))
+    }
+
+    pub fn status(&self, id: &JobId) -> Result<JobState, OpsError> {
+        Ok(ops::job(self.job_path(id)?)?.state)
+    }
+
+    /// Blocks until the task has finished and returns its status. Returns `None` if its queue file
+    /// was removed by `clean` before the status could be read
+    pub fn wait(&self, id: &JobId) -> Result<Option<JobStatus>, OpsError> {
+        ops::wait_for_status(&self.job_path(id)?)
+    }
+
+    /// The job that `reference` refers to: a queue file name, a job's name (the most recent job if
+    /// several share it), or a prefix of either that only one job matches
+    pub fn find<S: AsRef<ffi::OsStr>>(&self, reference: S) -> Result<JobId, OpsError> {
+        let job_path = ops::find(&self.dir, reference.as_ref())?;
+        let filename = job_path.file_name().unwrap_or_default();
+        Ok(JobId::from(filename.to_os_string()))
+    }
+
+    /// Every job in the queue, in queue order
+    pub fn list(&self) -> Result<Vec<Job>, OpsError> {
+        ops::list(&self.dir)
+    }
+
+    /// Lifecycle events of every job in the queue from now on. Blocks while waiting for the next
+    pub fn events(&self) -> Result<Events, OpsError> {
+        Events::new(&self.dir)
+    }
+
+    /// Reads the task's queue file as it is being written. Reaches EOF once the task has finished
+    pub fn follow(&self, id: &JobId) -> Result<ops::Follow, OpsError> {
+        ops::Follow::open(&self.job_path(id)?)
+    }
+}
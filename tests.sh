@@ -1,7 +1,9 @@
 #!/usr/bin/env bash
 
 set -e
-unset FNQ_DIR
+unset FNQ_DIR FNQ_GC FNQ_LOCK_DIR FNQ_CGROUP
+# Keeps the config file of whoever runs the tests out of them
+export XDG_CONFIG_HOME=/nonexistent
 
 : ${FNQ:="cargo --quiet run --"}
 
@@ -102,4 +104,410 @@ sleep 1
 )
 teardown
 
+setup
+(
+printf '\n# --gc tests\n'
+check 'enqueueing true' 'f1=$($FNQ true)'
+check 'enqueueing false' 'f2=$($FNQ false)'
+check 'enqueueing true' 'f3=$($FNQ true)'
+check 'enqueueing sleep 100' 'f4=$($FNQ sleep 100)'
+sleep 1
+check '--gc --only-succeeded removes succeeded jobs' '$FNQ --gc --only-succeeded --keep-last 1 | grep -q $f1'
+check 'kept failed job' test -f $f2
+check 'kept last succeeded job' test -f $f3
+check '--gc --older-than keeps recent jobs' '$FNQ --gc --older-than 1d; test -f $f2'
+check '--gc removes finished jobs' '$FNQ --gc; ! test -f $f2'
+check '--gc never removes running jobs' test -f $f4
+check 'FNQ_GC runs on enqueue' 'kill ${f4##*.}; sleep 1; FNQ_GC="--keep-last 0" $FNQ -q true; ! test -f $f4'
+)
+teardown
+
+setup
+(
+printf '\n# --clean tests\n'
+check '--clean removes succeeded job' 'f1=$($FNQ --clean true); $FNQ --block; sleep 1; ! test -f $f1'
+check '--clean keeps failed job' 'f2=$($FNQ --clean false); $FNQ --block; sleep 1; test -f $f2'
+check '--clean=failure removes failed job' 'f3=$($FNQ --clean=failure false); $FNQ --block; sleep 1; ! test -f $f3'
+check '--clean=always removes succeeded job' 'f4=$($FNQ --clean=always true); $FNQ --block; sleep 1; ! test -f $f4'
+check '--archive gzips removed job' 'f5=$($FNQ --clean --archive echo archived); $FNQ --block; sleep 1; ! test -f $f5 && zcat archive/$f5.gz | grep -q archived'
+check '--keep-last keeps recent jobs' 'for i in 1 2 3; do $FNQ -q true; done; f6=$($FNQ --keep-last 2 true); $FNQ --block; sleep 1; test -f $f6 && test $(ls fnq* | wc -l) -eq 2'
+)
+teardown
+
+setup
+(
+printf '\n# --compress tests\n'
+check 'enqueueing compressed job' 'f1=$($FNQ --compress echo compressed)'
+$FNQ --block
+sleep 1
+check 'queue file is gzipped' 'zcat $f1 | grep -q exited.*status.*0'
+check '--last reads compressed queue file' '$FNQ --last | grep -q compressed'
+check 'enqueueing compressed job before another' 'f2=$($FNQ --compress sh -c "sleep 0.5; echo first") && f3=$($FNQ echo second) && $FNQ --block && sleep 1'
+check 'compressed job keeps its place for --last' '$FNQ --last | grep -q second'
+check 'compressed job keeps its place for --list' 'test "$($FNQ --list | cut -f1 | grep -e $f2 -e $f3 | tr "\n" " ")" = "$f2 $f3 "'
+check '--gc reads compressed status' '$FNQ --gc --only-succeeded | grep -q $f1'
+)
+teardown
+
+setup
+(
+printf '\n# --repair tests\n'
+check 'enqueueing sleep 1' 'f1=$($FNQ sleep 1)'
+check 'killing supervisor' 'kill -9 $(ps -o ppid= -p ${f1##*.})'
+sleep 2
+check '--repair marks lost job' '$FNQ --repair --requeue | grep -q $f1'
+check 'lost job contains status line' grep -q supervisor.*lost $f1
+check '--repair requeued lost job' 'test $(ls fnq* | wc -l) -eq 2'
+check '--list shows lost job' '$FNQ --list | grep $f1 | grep -q supervisor.lost'
+)
+teardown
+
+setup
+(
+printf '\n# --resume tests\n'
+check 'enqueueing sleep 100' 'f1=$($FNQ sleep 100)'
+check 'enqueueing pwd' 'mkdir work; f2=$(cd work && FNQ_DIR=.. $FNQ pwd)'
+check 'dropping supervisors and tasks' 'kill -9 $(ps -o ppid= -p ${f1##*.}) $(ps -o ppid= -p ${f2##*.}); kill -9 ${f2##*.} ${f1##*.}'
+sleep 1
+check '--resume requeues lost jobs in order' '$FNQ --resume | sed 1q | grep -q $f1'
+check '--resume is idempotent' 'test -z "$($FNQ --resume)"'
+check 'resumed job is running' 'f3=$(ls fnq* | sed -n 3p); ! $FNQ --tap $f3'
+check 'killing resumed job' 'kill ${f3##*.}'
+sleep 1
+check 'resumed job keeps its cwd' 'grep -q work$ $(ls fnq* | sed -n 4p)'
+check 'enqueueing task named like a subcommand' 'mkdir bin && printf "#!/bin/sh\nexec sleep 100\n" > bin/ls && chmod +x bin/ls && f4=$(PATH=$PWD/bin:$PATH $FNQ -- ls -la)'
+check 'dropping its supervisor and task' 'kill -9 $(ps -o ppid= -p ${f4##*.}); kill -9 ${f4##*.}'
+sleep 1
+check '--resume requeues task named like a subcommand' 'f5=$(PATH=$PWD/bin:$PATH $FNQ --resume) && f5=${f5##* } && head -1 $f5 | grep -q "^exec .* -- ls -la$"'
+check 'resumed task named like a subcommand is running' '! $FNQ --tap $f5 && kill ${f5##*.}'
+check 'enqueueing job before its config has defaults' 'mkdir -p config/fnq && f6=$(XDG_CONFIG_HOME=$PWD/config $FNQ sleep 100)'
+check 'dropping its supervisor and task' 'kill -9 $(ps -o ppid= -p ${f6##*.}); kill -9 ${f6##*.}'
+sleep 1
+check 'resumed job keeps its flags over config defaults' 'printf "clean = \"always\"\ntimeout = \"1s\"\n" > config/fnq/config.toml && f7=$($FNQ --resume) && f7=${f7##* } && sleep 2 && ! $FNQ --tap $f7 && kill ${f7##*.}'
+check 'enqueueing job from a dir removed later' 'mkdir gone && f8=$(cd gone && FNQ_DIR=.. $FNQ sleep 100) && f9=$($FNQ sleep 100)'
+check 'dropping supervisors and tasks' 'kill -9 $(ps -o ppid= -p ${f8##*.}) $(ps -o ppid= -p ${f9##*.}); kill -9 ${f9##*.} ${f8##*.}; rm -r gone'
+sleep 1
+check '--resume carries on past a job it cannot requeue' 'f10=$($FNQ --resume); test $? -eq 1 && echo "$f10" | grep -q "$f9 -> " && f10=${f10##* }'
+check 'job it could not requeue is marked lost' 'grep -q "supervisor lost" $f8 && ! grep -q "requeued as" $f8'
+check 'killing job requeued past it' 'kill ${f10##*.}'
+)
+teardown
 
+setup
+(
+printf '\n# argument tests\n'
+check 'enqueueing arguments with spaces and quotes' "f1=\$(\$FNQ printf '%s|' 'a b' \"it's\" '')"
+check 'enqueueing non-UTF-8 argument' "f2=\$(\$FNQ printf '%s' \$(printf 'x\\377y'))"
+$FNQ --block
+check 'exec line is shell quoted' "grep -q \"^exec .* printf '%s|' 'a b' 'it'\\\\\\\\''s' ''\\$\" \$f1"
+check 'arguments reach the task unchanged' "grep -q \"^a b|it's||\" \$f1"
+check 'non-UTF-8 argument reaches the task' "grep -q \$(printf 'x\\377y') \$f2"
+)
+teardown
+
+setup
+(
+printf '\n# exec tests\n'
+check 'fails right away for missing command' '! $FNQ nosuchcommand'
+check 'nothing queued for missing command' '! ls fnq*'
+check 'fails right away for non-executable command' 'touch noexec; ! $FNQ ./noexec'
+check 'enqueueing script with missing interpreter' "printf '#!/nonexistent\\n' > badshebang; chmod +x badshebang; f1=\$(\$FNQ ./badshebang)"
+$FNQ --block
+check 'exec failure contains status line' grep -q 'exec.failed:.ENOENT' $f1
+check 'exec failure does not panic' ! grep -q panicked $f1
+)
+teardown
+
+setup
+(
+printf '\n# events tests\n'
+$FNQ --events > events.log 2>/dev/null &
+events_pid=$!
+sleep 0.5
+check 'enqueueing job' "f1=\$(\$FNQ sh -c 'sleep 0.5; echo hi; sleep 0.5')"
+check 'enqueueing cleaned job' 'f2=$($FNQ -c true)'
+$FNQ --block
+sleep 0.5
+kill $events_pid
+check 'enqueued event' "grep -q \"\$f1.enqueued\" events.log"
+check 'started event' "grep -q \"\$f1.started\" events.log"
+check 'output event' "grep -q \"\$f1.output\" events.log"
+check 'exited event has status' "grep -q \"\$f1.exited.exited 0\" events.log"
+check 'removed event' "grep -q \"\$f2.removed\" events.log"
+check 'events are in order' "grep \"\$f1\" events.log | cut -f3 | uniq | paste -sd ' ' - | grep -qx 'enqueued started output exited'"
+)
+teardown
+
+setup
+(
+printf '\n# hook tests\n'
+mkdir hooks
+printf '#!/bin/sh\necho "$FNQ_HOOK $FNQJOBID" >> hooks.log\n' > hooks/on-start
+cp hooks/on-start hooks/on-success
+printf '#!/bin/sh\nexit 1\n' > hooks/on-failure
+chmod +x hooks/*
+check 'enqueueing job with --on-success' "f1=\$(\$FNQ --on-success 'echo \$FNQ_EXIT_CODE \$FNQ_DURATION \$FNQ_QUEUE_FILE > success.log' sleep 0.2)"
+check 'enqueueing killed job with --on-failure' "f2=\$(\$FNQ --on-failure 'echo \$FNQ_EXIT_CODE > failure.log' sleep 100)"
+sleep 1
+check 'killing second job' kill ${f2##*.}
+$FNQ --block
+# Hooks run after the queue files are unlocked
+sleep 0.5
+check 'on-start hook ran' "grep -qx \"on-start \$f1\" hooks.log"
+check 'on-success hook ran' "grep -qx \"on-success \$f1\" hooks.log"
+check '--on-success gets exit code, duration and path' "grep -qx \"0 0\\.[0-9]* \$PWD/\$f1\" success.log"
+check '--on-failure gets 128 plus signal' 'grep -qx 143 failure.log'
+check 'failing hook is recorded' 'grep -q "^.hook on-failure failed: exit status: 1" $f2'
+check 'failing hook does not hide status' '$FNQ --list | grep $f2 | grep -q "killed SIGTERM"'
+check 'enqueueing job behind a running one' "f3=\$(\$FNQ sleep 100) && f4=\$(\$FNQ --on-failure 'echo ran > cancelled.log' true)"
+check 'killing waiting job' '$FNQ kill $f4'
+check 'killed waiting job is cancelled' '$FNQ wait $f4; $FNQ ls | grep $f4 | grep -q cancelled'
+check 'cancelled job runs no hooks' 'kill ${f3##*.} && $FNQ --block; sleep 0.5; ! test -e cancelled.log && ! grep -q $f4 hooks.log'
+)
+teardown
+
+setup
+(
+printf '\n# exit code tests\n'
+check 'usage error exits 2' '$FNQ --tap a b; test $? -eq 2'
+check 'missing queue file exits 3' '$FNQ --tap nosuchfile; test $? -eq 3'
+check 'missing queue file message names the path' '$FNQ --block nosuchfile 2>&1 | grep -q nosuchfile'
+check 'missing command exits 127' '$FNQ nosuchcommand; test $? -eq 127'
+check 'non-executable command exits 126' 'touch noexec; $FNQ ./noexec; test $? -eq 126'
+check 'FNQ_DIR that is a file exits 4' 'touch notadir; FNQ_DIR=notadir $FNQ --list; test $? -eq 4'
+check 'enqueueing failing task' "f1=\$(\$FNQ false)"
+check 'blocking on failed task exits 6' '$FNQ --block $f1; test $? -eq 6'
+check 'blocking on succeeded task exits 0' 'f2=$($FNQ true) && $FNQ --block $f2'
+)
+teardown
+
+setup
+(
+printf '\n# subcommand tests\n'
+check 'add takes combined flags and --' 'test -z "$($FNQ add -qc -- true)"'
+check 'add queues a job' 'f1=$($FNQ add sh -c "sleep 100; true")'
+check 'add queues a second job' 'f2=$($FNQ add echo hi)'
+check 'ls shows running job' '$FNQ ls | grep $f1 | grep -q running'
+check 'ls shows waiting job' '$FNQ ls | grep $f2 | grep -q waiting'
+check 'rm refuses running job and exits 7' '$FNQ rm $f1; test $? -eq 7'
+check 'kill --signal kills the job' '$FNQ kill --signal KILL $f1'
+check 'wait blocks on second job' '$FNQ wait $f2'
+check 'kill reached the job' '$FNQ wait $f1; $FNQ ls | grep $f1 | grep -q "killed SIGKILL"'
+check 'tail prints finished job' '$FNQ tail $f2 | grep -qx hi'
+check 'kill on finished job exits 7' '$FNQ kill $f1; test $? -eq 7'
+check 'rm removes finished jobs' '$FNQ rm $f1 $f2 && ! test -f $f1 && ! test -f $f2'
+check 'rm refuses other files and exits 3' 'touch build.log && $FNQ rm build.log; test $? -eq 3 && test -f build.log'
+check 'rm can be queued with --' 'f3=$($FNQ -- rm build.log) && $FNQ wait $f3 && ! test -f build.log'
+check 'tail follows running job' "f3=\$(\$FNQ add sh -c 'sleep 0.5; echo later') && \$FNQ tail \$f3 | grep -qx later"
+check 'subcommand help' '$FNQ kill --help | grep -q signal'
+check 'help subcommand' '$FNQ help rm | grep -q "fnq rm"'
+check 'subcommand usage error exits 2' '$FNQ wait a b; test $? -eq 2'
+)
+teardown
+
+setup
+(
+printf '\n# dir and config tests\n'
+mkdir -p config/fnq
+export XDG_CONFIG_HOME=$PWD/config
+printf 'dir = "%s/default"\nquiet = true\n\n[queues.builds]\ndir = "%s/builds"\nclean = "always"\ntimeout = "1s"\n' "$PWD" "$PWD" > config/fnq/config.toml
+check '--dir wins over FNQ_DIR' 'FNQ_DIR=env $FNQ --dir flag add true && test -n "$(ls flag)" && ! test -d env'
+check 'config default flags apply' 'test -z "$($FNQ true)"'
+check '--no-quiet wins over config quiet' 'test -n "$($FNQ --no-quiet true)"'
+check 'config dir is used' '$FNQ --block && test -n "$(ls default)"'
+check 'FNQ_DIR wins over config dir' 'FNQ_DIR=env $FNQ -q true && test -n "$(ls env)"'
+check 'named queue uses its dir and flags' '$FNQ --queue builds true && $FNQ --queue builds wait && sleep 0.5 && test -d builds && test -z "$(ls builds | grep ^fnq)"'
+check 'timeout kills the job' '$FNQ --queue builds --dir timeout --clean=never sleep 10 && $FNQ --dir timeout wait && $FNQ --dir timeout ls | grep -q "timed out after 1s"'
+check 'timeout kills the whole process group' "\$FNQ --dir timeout --timeout 1 sh -c 'sleep 1000 & echo \$! > pid; wait' && \$FNQ --dir timeout wait && sleep 0.5 && ! ps -o stat= -p \$(cat pid) | grep -qv '^Z'"
+check 'unknown queue exits 2' '$FNQ --queue nosuchqueue ls; test $? -eq 2'
+check 'invalid config exits 2' 'echo "quite = true" > config/fnq/config.toml; $FNQ ls; test $? -eq 2'
+check 'help and version ignore the config' '$FNQ --help > /dev/null && $FNQ -v > /dev/null && $FNQ --queue nosuchqueue add -h > /dev/null'
+)
+teardown
+
+setup
+(
+printf '\n# name and label tests\n'
+check 'enqueueing named job' 'f1=$($FNQ --name docs --label team=web --label kind=build sh -c "sleep 0.5; echo docs")'
+check 'enqueueing unlabeled job' 'f2=$($FNQ --name other true)'
+check '--tap takes a name' '! $FNQ --tap docs'
+check '--block takes a name' '$FNQ --block docs'
+check 'tail takes a unique prefix of a name' '$FNQ tail do | grep -qx docs'
+check 'ambiguous prefix exits 3' '$FNQ --tap fnq; test $? -eq 3'
+check 'only queue files are found' 'touch notes.txt && r=0 && for ref in notes.txt ./$f1 $PWD/$f1 ../$(basename $PWD)/$f1; do $FNQ --tap $ref; test $? -eq 3 || r=1; done; test $r -eq 0'
+check 'list shows name and labels' '$FNQ --list | grep $f1 | grep -q "docs	team=web kind=build"'
+check 'list filters by label' 'test "$($FNQ ls --label team=web --label kind=build | cut -f1)" = $f1'
+check 'list filter leaves out unlabeled jobs' '! $FNQ --list --label team=web | grep -q $f2'
+check 'most recent job with a name wins' 'f3=$($FNQ --name docs true) && $FNQ --block docs && $FNQ rm docs && ! test -f $f3 && test -f $f1'
+)
+teardown
+
+setup
+(
+printf '\n# batch tests\n'
+check '--batch-start prints a batch name' 'b=$($FNQ --batch-start) && test -n "$b"'
+check 'enqueueing batch jobs' 'f1=$($FNQ --batch $b sleep 0.5) && f2=$($FNQ --batch $b false)'
+check 'enqueueing job outside the batch' 'f3=$($FNQ sleep 100)'
+check '--tap --batch sees running batch' '$FNQ --tap --batch $b; test $? -eq 1'
+check '--block --batch exits 6 on a failed job' '$FNQ --block --batch $b; test $? -eq 6'
+check '--block --batch leaves other jobs alone' '$FNQ --tap $f3; test $? -eq 1'
+check '--tap --batch sees finished batch' '$FNQ --tap --batch $b'
+check 'wait --batch on succeeded batch' 'b2=$($FNQ --batch-start) && kill ${f3##*.} && $FNQ -q --batch $b2 true && $FNQ wait --batch $b2'
+)
+teardown
+
+setup
+(
+printf '\n# barrier tests\n'
+check 'enqueueing jobs before barrier' "f1=\$(\$FNQ sh -c 'sleep 0.5; exit 3') && f2=\$(\$FNQ true)"
+check 'enqueueing barrier' 'b1=$($FNQ --barrier) && head -1 $b1 | grep -q -- "--barrier$"'
+check 'enqueueing job after barrier' 'f3=$($FNQ true)'
+check 'job after barrier waits' '$FNQ --tap $f3; test $? -eq 1'
+check 'barrier fails if a job before it failed' '$FNQ --block $b1; test $? -eq 6'
+check 'barrier lists failed jobs' 'grep -q "^$f1	exited 3$" $b1 && ! grep -q $f2 $b1'
+check 'barrier only checks jobs since previous barrier' 'b2=$($FNQ --barrier) && $FNQ --block $b2'
+check 'barrier takes queue flags' '$FNQ -q sleep 0.5 && $FNQ -q --name phase --clean --barrier && $FNQ --block phase'
+)
+teardown
+
+setup
+(
+printf '\n# schedule tests\n'
+check 'scheduling prints schedule id' "s1=\$(\$FNQ --every 1s sh -c 'sleep 1.5; echo tick') && test -f schedules/\$s1"
+check 'schedule queues right away' 'sleep 0.5 && test "$($FNQ --list | wc -l)" -eq 1'
+check 'schedule is listed as active' '$FNQ --schedules | grep "^$s1	active	--every 1s	" | grep -q "echo tick"'
+check 'run is skipped while previous is pending' 'sleep 1 && grep -q "	skipped	" schedules/$s1'
+check 'schedule queues again' 'sleep 1.5 && test "$($FNQ --list | wc -l)" -ge 2'
+check 'unschedule stops the scheduler' '$FNQ --unschedule $s1 && sleep 1.5 && n=$($FNQ --list | wc -l) && sleep 1.5 && test "$($FNQ --list | wc -l)" -eq $n'
+check 'unschedule unknown schedule exits 3' '$FNQ --unschedule $s1; test $? -eq 3'
+check 'invalid cron expression is a usage error' '$FNQ --cron "* * *" true; test $? -eq 2'
+check 'cron schedule waits for its minute' 's2=$($FNQ add --cron "@yearly" true) && $FNQ --schedules | grep -q "^$s2	active	--cron @yearly	true	$" && $FNQ --unschedule $s2'
+)
+teardown
+
+setup
+(
+printf '\n# on-change tests\n'
+mkdir src
+# The queue dir is inside the watched dir
+export FNQ_DIR=src
+$FNQ --on-change src -- sh -c 'sleep 3; echo ran' > queued.txt &
+watcher=$!
+trap 'kill $watcher' EXIT
+sleep 0.5
+check 'nothing queued before a change' 'test -z "$($FNQ --list)"'
+check 'change queues command' 'touch src/a && sleep 1 && test "$($FNQ --list | wc -l)" -eq 1'
+check 'burst of changes queues once' 'for f in b c d e; do touch src/$f; done && sleep 1 && test "$($FNQ --list | wc -l)" -eq 2'
+check 'change coalesces into waiting run' 'touch src/f && sleep 1 && test "$($FNQ --list | wc -l)" -eq 2'
+check 'queue files do not trigger runs' '$FNQ --block && sleep 1 && test "$($FNQ --list | wc -l)" -eq 2'
+check 'queued files are printed' 'test "$(wc -l < queued.txt)" -eq 2'
+check 'missing path exits 4' '$FNQ --on-change nosuchdir true; test $? -eq 4'
+)
+teardown
+
+setup
+(
+printf '\n# unique tests\n'
+check 'enqueueing blocker' 'f1=$($FNQ sleep 100)'
+check 'enqueueing waiting job' 'f2=$($FNQ --unique sh -c "echo one")'
+check '--unique prints waiting duplicate' 'test "$($FNQ --unique sh -c "echo one")" = $f2'
+check '--unique queues different arguments' 'f3=$($FNQ --unique --on-failure "touch replaced" sh -c "echo two") && test $f3 != $f2'
+check '--unique queues from another directory' 'mkdir sub && f4=$(cd sub && $FNQ --dir .. --unique sh -c "echo one") && test $f4 != $f2'
+check '--replace removes waiting duplicate' 'f5=$($FNQ --replace sh -c "echo two") && test $f5 != $f3 && ! test -f $f3'
+check 'replaced job runs no hooks' 'sleep 0.5 && ! test -e replaced && ! test -e meta/$f3 && ! test -e meta/$f3.cancelled'
+check '--unique ignores started jobs' 'f6=$($FNQ --unique sleep 100) && test $f6 != $f1'
+check 'cleaning up' 'kill ${f1##*.} && kill ${f6##*.} && $FNQ --block; true'
+)
+teardown
+
+setup
+(
+printf '\n# lock tests\n'
+export FNQ_LOCK_DIR=$PWD/locks
+check 'enqueueing locked job' "f1=\$(\$FNQ --dir q1 --lock db sh -c 'echo a-start >> log; sleep 1; echo a-end >> log')"
+check 'enqueueing job with same lock in another queue' "f2=\$(\$FNQ --dir q2 --lock db sh -c 'echo b-start >> log; echo b-end >> log')"
+check 'job waits for lock held by another queue' 'sleep 0.5 && $FNQ --dir q2 ls | grep $f2 | grep -q waiting'
+check 'jobs with same lock never overlap' '$FNQ --dir q1 --block && $FNQ --dir q2 --block && test "$(cat log)" = "$(printf "a-start\na-end\nb-start\nb-end")"'
+check 'lock is kept for --resume' 'grep -A1 "^option --lock$" q1/meta/$f1 | grep -q "^option db$"'
+check 'enqueueing jobs sharing two slots' 'for q in s1 s2 s3; do $FNQ --dir $q -q --lock gpu:2 sleep 1; done'
+check 'only two of them run at once' 'sleep 0.5 && test "$(for q in s1 s2 s3; do $FNQ --dir $q ls; done | grep -c running)" -eq 2'
+check 'third runs once a slot frees up' 'for q in s1 s2 s3; do $FNQ --dir $q --block; done'
+check 'invalid lock is a usage error' '$FNQ --lock gpu:0 true; test $? -eq 2'
+)
+teardown
+
+setup
+(
+printf '\n# admission tests\n'
+check 'job starts once enough memory is available' 'f1=$($FNQ --min-memory 1K --max-load 1000 true) && $FNQ --block $f1'
+check 'enqueueing job needing more memory than there is' 'f2=$($FNQ --min-memory 1000000T true)'
+check 'job waits for memory' 'sleep 1.5 && $FNQ ls | grep $f2 | grep -q waiting'
+check 'admission is kept for --resume' 'grep -q "^option 1099511627776000000$" meta/$f2'
+check 'waiting job can be killed' 'kill ${f2##*.} && $FNQ --block $f2; test $? -eq 6'
+check 'job waiting for memory holds no lock' 'f3=$(FNQ_LOCK_DIR=locks $FNQ --dir q3 --lock db --min-memory 1000000T true) && sleep 0.5 && f4=$(FNQ_LOCK_DIR=locks $FNQ --dir q4 --lock db true) && timeout 5 $FNQ --dir q4 --block $f4'
+check 'cleaning up' 'kill ${f3##*.} && $FNQ --dir q3 --block; true'
+check 'invalid size is a usage error' '$FNQ --min-memory lots true; test $? -eq 2'
+check 'invalid load is a usage error' '$FNQ --max-load 0 true; test $? -eq 2'
+)
+teardown
+
+setup
+(
+printf '\n# limit tests\n'
+check 'limits are applied to the process' "f1=\$(\$FNQ --limit-mem 512M --limit-nofile 64 --limit-core 0 sh -c 'ulimit -v; ulimit -n; ulimit -c') && \$FNQ --block \$f1 && test \"\$(sed -n '3,5p' \$f1 | tr '\n' ' ')\" = '524288 64 0 '"
+check 'enqueueing job using too much CPU' "f2=\$(\$FNQ --limit-cpu 1s sh -c 'while :; do :; done')"
+check 'job over --limit-cpu fails' '$FNQ --block $f2; test $? -eq 6'
+check 'status line says which limit was exceeded' 'grep -q "^\[killed by signal: SIGXCPU (exceeded --limit-cpu 1s)\]$" $f2'
+check 'job ignoring SIGXCPU is killed over --limit-cpu' "f3=\$(\$FNQ --limit-cpu 1s sh -c 'trap \"\" XCPU; while :; do :; done') && \$FNQ --block \$f3; grep -q '^\[killed by signal: SIGKILL (exceeded --limit-cpu 1s)\]$' \$f3"
+check 'limit above hard limit fails' '$FNQ --limit-nofile 99999999999 true; test $? -eq 70'
+check 'invalid limit is a usage error' '$FNQ --limit-cpu forever true; test $? -eq 2'
+)
+teardown
+
+setup
+(
+printf '\n# priority tests\n'
+check 'nice value is applied to the process' 'f1=$($FNQ --nice 10 nice) && $FNQ --block $f1 && test "$(sed -n 3p $f1)" = 10'
+check 'IO class is applied to the process' 'f2=$($FNQ --ionice idle ionice) && $FNQ --block $f2 && test "$(sed -n 3p $f2)" = idle'
+check 'ls shows the priority' '$FNQ ls | grep $f1 | grep -q "nice=10$" && $FNQ ls | grep $f2 | grep -q "ionice=idle$"'
+check 'priority is kept in metadata' 'grep -q "^nice 10$" meta/$f1 && grep -q "^ionice idle$" meta/$f2'
+check 'nice value out of range is a usage error' '$FNQ --nice 20 true; test $? -eq 2'
+check 'invalid IO class is a usage error' '$FNQ --ionice idle:3 true; test $? -eq 2'
+# Root has to drop the capabilities first
+unprivileged=
+test "$(id -u)" -eq 0 && unprivileged='setpriv --bounding-set -sys_nice,-sys_admin'
+check 'negative nice without privilege exits 70' '$unprivileged $FNQ --nice -5 true; test $? -eq 70'
+check 'realtime IO class without privilege exits 70' '$unprivileged $FNQ --ionice realtime true; test $? -eq 70'
+check 'nothing was queued for them' 'test $(ls fnq* | wc -l) -eq 2'
+)
+teardown
+
+setup
+(
+printf '\n# cgroup tests\n'
+cgroup=$(sed -n 's/^0:://p' /proc/self/cgroup)
+cgroup_dir=$(grep -m1 ' - cgroup2 ' /proc/self/mountinfo | cut -d' ' -f5)$cgroup
+if [ ! -w "$cgroup_dir/cgroup.procs" ]; then
+  printf 'skipped, no cgroup v2 hierarchy that can be written to\n'
+  check 'cgroups need a cgroup' '$FNQ --cgroup true; test $? -eq 70'
+  exit
+fi
+check 'jobs get no cgroup by default' 'f0=$($FNQ cat /proc/self/cgroup) && $FNQ --block $f0 && grep -q "^0::$cgroup$" $f0'
+check 'job runs in a cgroup of its own' 'f1=$($FNQ --cgroup cat /proc/self/cgroup) && $FNQ --block $f1 && grep -q "^0::.*/$f1$" $f1'
+check 'cgroup is removed once the job exits' 'sleep 0.5 && test ! -e $cgroup_dir/$f1'
+check 'enqueueing job that leaves a process behind' "f2=\$(\$FNQ --cgroup --timeout 1 sh -c 'setsid sleep 1000 & echo \$! > pid; sleep 1000')"
+check 'timed out job fails' '$FNQ --block $f2; test $? -eq 6'
+check 'what the job left behind is killed with it' 'sleep 0.5 && ! ps -o stat= -p $(cat pid) | grep -qv "^Z"'
+check 'cgroup outlives what the job left behind' 'f3=$($FNQ --cgroup sh -c "sleep 1 > /dev/null &") && $FNQ --block $f3 && test -d $cgroup_dir/$f3'
+check 'cgroup is removed once that exited' 'sleep 2 && test ! -e $cgroup_dir/$f3'
+check 'enqueueing job whose supervisor dies' 'f4=$($FNQ --cgroup sleep 1) && sleep 0.5 && kill -9 $(ps -o ppid= -p ${f4##*.})'
+check '--repair removes the cgroup of the lost job' 'sleep 2 && $FNQ --repair | grep -q $f4 && test ! -e $cgroup_dir/$f4'
+check 'empty FNQ_CGROUP turns cgroups off' 'FNQ_CGROUP= $FNQ --cgroup true; test $? -eq 70'
+if grep -qw memory $cgroup_dir/cgroup.subtree_control; then
+  check 'job runs under --memory-max' 'f5=$($FNQ --memory-max 64M true) && $FNQ --block $f5'
+else
+  check 'limits fail without their controller' '$FNQ --memory-max 1G true; test $? -eq 70'
+fi
+check 'invalid cpu limit is a usage error' '$FNQ --cpu-max 0 true; test $? -eq 2'
+)
+teardown
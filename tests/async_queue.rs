@@ -0,0 +1,38 @@
+#![cfg(feature = "async")]
+
+use std::pin::Pin;
+use std::{env, fs, process};
+
+use fnq::{Command, FollowStream, JobStatus, Queue};
+use futures_core::Stream;
+
+async fn collect(mut stream: FollowStream) -> Vec<u8> {
+    let mut output = Vec::new();
+    while let Some(chunk) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        output.extend(chunk.unwrap());
+    }
+    output
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_async_queue() {
+    // Tasks are queued by the fnq binary
+    env::set_var("FNQ_BIN", env!("CARGO_BIN_EXE_fnq"));
+    let dir = env::temp_dir().join(format!("fnq-async-test-{}", process::id()));
+    let queue = Queue::open(&dir).unwrap();
+
+    let first = queue
+        .enqueue(Command::new("sh").args(["-c", "sleep 0.3; echo first"]))
+        .unwrap();
+    let second = queue.enqueue(&Command::new("false")).unwrap();
+
+    // Both run on the one thread, so neither may block it
+    let (output, second_status) = tokio::join!(
+        collect(queue.follow_async(&first).unwrap()),
+        queue.wait_async(&second)
+    );
+    assert!(String::from_utf8_lossy(&output).contains("\nfirst\n[exited with status 0.]\n"));
+    assert_eq!(second_status.unwrap(), Some(JobStatus::Exited(1)));
+
+    fs::remove_dir_all(dir).unwrap();
+}
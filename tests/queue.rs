@@ -0,0 +1,55 @@
+use std::io::Read;
+use std::{env, fs, process};
+
+use fnq::{Command, DuplicatePolicy, Job, JobId, JobState, JobStatus, Queue};
+
+#[test]
+fn test_queue() {
+    // Tasks are queued by the fnq binary
+    env::set_var("FNQ_BIN", env!("CARGO_BIN_EXE_fnq"));
+    let dir = env::temp_dir().join(format!("fnq-test-{}", process::id()));
+    let queue = Queue::open(&dir).unwrap();
+
+    let first = queue
+        .enqueue(Command::new("sh").args(["-c", "sleep 0.5; echo first"]))
+        .unwrap();
+    let second = queue
+        .enqueue(
+            Command::new("sh")
+                .args(["-c", "echo second; exit 3"])
+                .name("second")
+                .label("kind", "test"),
+        )
+        .unwrap();
+    assert_eq!(queue.find("second").unwrap(), second);
+    assert_eq!(queue.find("sec").unwrap(), second);
+    assert!(queue.find("fnq").is_err());
+    assert_eq!(queue.status(&second).unwrap(), JobState::Waiting);
+    let duplicate = queue
+        .enqueue(
+            Command::new("sh")
+                .args(["-c", "echo second; exit 3"])
+                .duplicates(DuplicatePolicy::Unique),
+        )
+        .unwrap();
+    assert_eq!(duplicate, second);
+
+    let mut output = String::new();
+    queue
+        .follow(&first)
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert!(output.contains("\nfirst\n[exited with status 0.]\n"));
+
+    assert_eq!(queue.wait(&second).unwrap(), Some(JobStatus::Exited(3)));
+    let barrier = queue.enqueue_barrier().unwrap();
+    assert_eq!(queue.wait(&barrier).unwrap(), Some(JobStatus::Exited(1)));
+    let ids: Vec<JobId> = queue.list().unwrap().iter().map(Job::id).collect();
+    assert_eq!(ids, vec![first, second, barrier]);
+    let labels = vec![("kind".into(), "test".into())];
+    assert_eq!(queue.list().unwrap()[1].labels, labels);
+    assert!(queue.status(&JobId::from("fnq0.0")).is_err());
+
+    fs::remove_dir_all(dir).unwrap();
+}